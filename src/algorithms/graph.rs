@@ -1,8 +1,29 @@
-use std::collections::{HashMap, HashSet, BinaryHeap};
+use std::collections::{HashMap, HashSet, BinaryHeap, VecDeque};
 use std::cmp::Ordering;
-use rayon::prelude::*;
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
 use petgraph::graph::{Graph, NodeIndex};
+use petgraph::Direction;
+use petgraph::visit::EdgeRef;
 use num_complex::Complex64;
+use parking_lot::Mutex;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum GraphError {
+    #[error("duplicate node id: {0}")]
+    DuplicateId(usize),
+    #[error("cancelled")]
+    Cancelled,
+    #[error("graph has a cycle")]
+    Cycle,
+    #[error("node limit of {0} reached")]
+    NodeLimit(usize),
+    #[error("edge limit of {0} reached")]
+    EdgeLimit(usize),
+}
 
 #[derive(Debug)]
 pub struct Nd {
@@ -11,76 +32,1522 @@ pub struct Nd {
     pos: Complex64,
 }
 
-#[derive(Debug)]
-pub struct Ed {
-    wt: f64,
-    flow: f64,
-}
+#[derive(Debug)]
+pub struct Ed {
+    wt: f64,
+    flow: f64,
+}
+
+/// Reported by `dfs_visit` alongside the node it concerns; edge events carry
+/// the edge's target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum VisitEvent {
+    Discover,
+    Finish,
+    TreeEdge(usize),
+    BackEdge(usize),
+}
+
+/// Summary of edge weights; all zero for a graph without edges.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct WeightStats {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub std_dev: f64,
+}
+
+/// Most odd-degree nodes `Grf::chinese_postman` will pair.
+pub const POSTMAN_MAX_ODD: usize = 20;
+
+type Canonical = (Vec<(usize, u64)>, Vec<(usize, usize, u64)>);
+
+pub struct Grf {
+    g: Graph<Nd, Ed>,
+    idx_map: HashMap<usize, NodeIndex>,
+    tree: Option<Vec<(usize, usize, f64)>>,
+    uf: Mutex<Option<UnionFind>>,
+    max_nodes: usize,
+    max_edges: usize,
+}
+
+impl Grf {
+    pub fn new() -> Self {
+        Self::with_limits(usize::MAX, usize::MAX)
+    }
+
+    /// A graph whose `add_nd` and `add_ed` fail once it holds `max_nodes`
+    /// nodes or `max_edges` edges, for building from untrusted input.
+    pub fn with_limits(max_nodes: usize, max_edges: usize) -> Self {
+        Self {
+            g: Graph::new(),
+            idx_map: HashMap::new(),
+            tree: None,
+            uf: Mutex::new(None),
+            max_nodes,
+            max_edges,
+        }
+    }
+
+    /// Nodes `0..n` with an edge each way between every pair, all weighted 1.
+    pub fn complete(n: usize) -> Self {
+        let mut g = Self::empty(n);
+        for u in 0..n {
+            for v in (0..n).filter(|&v| v != u) {
+                g.push_ed(u, v, 1.0);
+            }
+        }
+        g
+    }
+
+    /// Nodes `0..n` with edges `i -> i + 1` and `n - 1 -> 0`, all weighted 1.
+    /// A single node gets a self-loop.
+    pub fn cycle(n: usize) -> Self {
+        let mut g = Self::empty(n);
+        for u in 0..n {
+            g.push_ed(u, (u + 1) % n, 1.0);
+        }
+        g
+    }
+
+    /// Nodes `0..n` with edges `i -> i + 1`, all weighted 1.
+    pub fn path(n: usize) -> Self {
+        let mut g = Self::empty(n);
+        for u in 1..n {
+            g.push_ed(u - 1, u, 1.0);
+        }
+        g
+    }
+
+    /// Nodes `0..n` with each ordered pair of distinct nodes joined with
+    /// probability `edge_prob`, weighted uniformly in `[0, 1)`. The same seed
+    /// gives the same graph.
+    pub fn random(n: usize, edge_prob: f64, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut g = Self::empty(n);
+        for u in 0..n {
+            for v in (0..n).filter(|&v| v != u) {
+                if rng.gen::<f64>() < edge_prob {
+                    g.push_ed(u, v, rng.gen());
+                }
+            }
+        }
+        g
+    }
+
+    fn empty(n: usize) -> Self {
+        let mut g = Self::new();
+        for id in 0..n {
+            g.push_nd(id, 0.0, 0.0, 0.0);
+        }
+        g
+    }
+
+    pub fn add_nd(&mut self, id: usize, val: f64, x: f64, y: f64) -> Result<NodeIndex, GraphError> {
+        if self.g.node_count() >= self.max_nodes {
+            return Err(GraphError::NodeLimit(self.max_nodes));
+        }
+        Ok(self.push_nd(id, val, x, y))
+    }
+
+    pub fn add_ed(&mut self, from: usize, to: usize, wt: f64) -> Result<(), GraphError> {
+        if self.g.edge_count() >= self.max_edges {
+            return Err(GraphError::EdgeLimit(self.max_edges));
+        }
+        self.push_ed(from, to, wt);
+        Ok(())
+    }
+
+    fn push_nd(&mut self, id: usize, val: f64, x: f64, y: f64) -> NodeIndex {
+        let nd = Nd {
+            id,
+            val,
+            pos: Complex64::new(x, y),
+        };
+        let idx = self.g.add_node(nd);
+        self.idx_map.insert(id, idx);
+        *self.uf.get_mut() = None;
+        idx
+    }
+
+    fn push_ed(&mut self, from: usize, to: usize, wt: f64) {
+        let u = self.idx_map[&from];
+        let v = self.idx_map[&to];
+        self.g.add_edge(u, v, Ed { wt, flow: 0.0 });
+        self.tree = None;
+        *self.uf.get_mut() = None;
+    }
+
+    /// Adds an edge weighted by the Euclidean distance between the nodes.
+    pub fn add_ed_geo(&mut self, from: usize, to: usize) -> Result<(), GraphError> {
+        let d = (self.g[self.idx_map[&from]].pos - self.g[self.idx_map[&to]].pos).norm();
+        self.add_ed(from, to, d)
+    }
+
+    /// Weak connectivity (edge direction ignored), answered from a union-find
+    /// that is built on first use and discarded when nodes or edges are added.
+    pub fn connected(&self, a: usize, b: usize) -> bool {
+        let (Some(&u), Some(&v)) = (self.idx_map.get(&a), self.idx_map.get(&b)) else {
+            return false;
+        };
+
+        let mut uf = self.uf.lock();
+        let uf = uf.get_or_insert_with(|| {
+            let mut uf = UnionFind::new(self.g.node_count());
+            for e in self.g.edge_references() {
+                uf.union(e.source().index(), e.target().index());
+            }
+            uf
+        });
+        uf.connected(u.index(), v.index())
+    }
+
+    /// Whether `from` and `to` are still weakly connected after each of
+    /// `deletions` in turn, leaving the graph itself unchanged. Deleting
+    /// `(a, b)` removes every edge between the two nodes in either direction;
+    /// pairs without edges or with unknown ids change nothing. Computed
+    /// offline by adding the deleted edges back in reverse order.
+    pub fn connectivity_timeline(&self, from: usize, to: usize, deletions: &[(usize, usize)]) -> Vec<bool> {
+        let (Some(&s), Some(&t)) = (self.idx_map.get(&from), self.idx_map.get(&to)) else {
+            return vec![false; deletions.len()];
+        };
+        let pair = |a: usize, b: usize| {
+            let (u, v) = (self.idx_map.get(&a)?.index(), self.idx_map.get(&b)?.index());
+            Some((u.min(v), u.max(v)))
+        };
+
+        // Each pair is restored at its first deletion; later ones are no-ops.
+        let mut first = HashMap::new();
+        for (i, &(a, b)) in deletions.iter().enumerate() {
+            if let Some(p) = pair(a, b) {
+                first.entry(p).or_insert(i);
+            }
+        }
+
+        let mut uf = UnionFind::new(self.g.node_count());
+        let mut linked = HashSet::new();
+        for e in self.g.edge_references() {
+            let (u, v) = (e.source().index(), e.target().index());
+            let p = (u.min(v), u.max(v));
+            linked.insert(p);
+            if !first.contains_key(&p) {
+                uf.union(u, v);
+            }
+        }
+
+        let mut res = vec![false; deletions.len()];
+        for (i, &(a, b)) in deletions.iter().enumerate().rev() {
+            res[i] = uf.connected(s.index(), t.index());
+            if let Some(p) = pair(a, b) {
+                if first[&p] == i && linked.contains(&p) {
+                    uf.union(p.0, p.1);
+                }
+            }
+        }
+        res
+    }
+
+    pub fn relabel<F: Fn(usize) -> usize>(&mut self, f: F) -> Result<(), GraphError> {
+        let mut seen = HashSet::new();
+        let ids: Vec<usize> = self.g.node_weights().map(|nd| f(nd.id)).collect();
+        if let Some(&dup) = ids.iter().find(|&&id| !seen.insert(id)) {
+            return Err(GraphError::DuplicateId(dup));
+        }
+
+        self.idx_map.clear();
+        for (idx, id) in self.g.node_indices().zip(ids) {
+            self.g[idx].id = id;
+            self.idx_map.insert(id, idx);
+        }
+        self.tree = None;
+        Ok(())
+    }
+
+    pub fn contains_node(&self, id: usize) -> bool {
+        self.idx_map.contains_key(&id)
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.g.node_count()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.g.edge_count()
+    }
+
+    pub fn nodes_where<F: Fn(usize, f64) -> bool>(&self, pred: F) -> Vec<usize> {
+        self.g
+            .node_weights()
+            .filter(|nd| pred(nd.id, nd.val))
+            .map(|nd| nd.id)
+            .collect()
+    }
+
+    pub fn contains_edge(&self, from: usize, to: usize) -> bool {
+        match (self.idx_map.get(&from), self.idx_map.get(&to)) {
+            (Some(&u), Some(&v)) => self.g.contains_edge(u, v),
+            _ => false,
+        }
+    }
+
+    /// Weight of an edge from `from` to `to`; with parallel edges, any one.
+    pub fn edge_weight(&self, from: usize, to: usize) -> Option<f64> {
+        let e = self.g.find_edge(*self.idx_map.get(&from)?, *self.idx_map.get(&to)?)?;
+        Some(self.g[e].wt)
+    }
+
+    /// Applies `f` to every edge weight. Results are not checked; shortest
+    /// path and flow methods expect non-negative weights and no NaN.
+    pub fn map_weights<F: Fn(f64) -> f64>(&mut self, f: F) {
+        for e in self.g.edge_weights_mut() {
+            e.wt = f(e.wt);
+        }
+        self.tree = None;
+    }
+
+    pub fn neighbors(&self, id: usize) -> Vec<(usize, f64)> {
+        self.adjacent(id, Direction::Outgoing)
+    }
+
+    pub fn in_neighbors(&self, id: usize) -> Vec<(usize, f64)> {
+        self.adjacent(id, Direction::Incoming)
+    }
+
+    /// Outgoing neighbors and weights of every node; isolated nodes map to
+    /// an empty list.
+    pub fn to_adjacency_list(&self) -> HashMap<usize, Vec<(usize, f64)>> {
+        self.g
+            .node_weights()
+            .map(|nd| (nd.id, self.neighbors(nd.id)))
+            .collect()
+    }
+
+    fn adjacent(&self, id: usize, dir: Direction) -> Vec<(usize, f64)> {
+        let u = self.idx_map[&id];
+        self.g
+            .edges_directed(u, dir)
+            .map(|e| {
+                let other = if dir == Direction::Outgoing { e.target() } else { e.source() };
+                (self.g[other].id, e.weight().wt)
+            })
+            .collect()
+    }
+
+    fn copy_nodes(&self) -> Grf {
+        let mut res = Grf::new();
+        for nd in self.g.node_weights() {
+            res.push_nd(nd.id, nd.val, nd.pos.re, nd.pos.im);
+        }
+        res
+    }
+
+    /// Induced subgraph on `ids`; ids not present in the graph are ignored.
+    pub fn subgraph(&self, ids: &HashSet<usize>) -> Grf {
+        let mut res = Grf::new();
+        for nd in self.g.node_weights().filter(|nd| ids.contains(&nd.id)) {
+            res.push_nd(nd.id, nd.val, nd.pos.re, nd.pos.im);
+        }
+        for e in self.g.edge_references() {
+            let (from, to) = (self.g[e.source()].id, self.g[e.target()].id);
+            if ids.contains(&from) && ids.contains(&to) {
+                res.push_ed(from, to, e.weight().wt);
+            }
+        }
+        res
+    }
+
+    pub fn transpose(&self) -> Grf {
+        let mut res = self.copy_nodes();
+        for e in self.g.edge_references() {
+            res.push_ed(self.g[e.target()].id, self.g[e.source()].id, e.weight().wt);
+        }
+        res
+    }
+
+    /// Copies the graph with one edge each way between every pair of adjacent
+    /// nodes, weighted by the minimum over all edges between them in either
+    /// direction. Self-loops are kept once.
+    pub fn as_undirected(&self) -> Grf {
+        let mut res = self.copy_nodes();
+        for e in self.g.edge_references() {
+            let (u, v) = (e.source(), e.target());
+            match res.g.find_edge(u, v) {
+                Some(x) => {
+                    let wt = res.g[x].wt.min(e.weight().wt);
+                    res.g[x].wt = wt;
+                    if let Some(y) = res.g.find_edge(v, u) {
+                        res.g[y].wt = wt;
+                    }
+                }
+                None => {
+                    res.g.add_edge(u, v, Ed { wt: e.weight().wt, flow: 0.0 });
+                    if u != v {
+                        res.g.add_edge(v, u, Ed { wt: e.weight().wt, flow: 0.0 });
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    /// Scales each node's outgoing weights to sum to 1, giving transition
+    /// probabilities. Nodes without out-edges (absorbing states) or whose
+    /// weights sum to 0 are left unchanged.
+    pub fn row_normalize(&mut self) {
+        for u in self.g.node_indices() {
+            let total: f64 = self.g.edges(u).map(|e| e.weight().wt).sum();
+            if total == 0.0 {
+                continue;
+            }
+            let eds: Vec<_> = self.g.edges(u).map(|e| e.id()).collect();
+            for e in eds {
+                self.g[e].wt /= total;
+            }
+        }
+        self.tree = None;
+    }
+
+    /// Population standard deviation; the median of an even count is the
+    /// mean of the two middle weights.
+    pub fn weight_stats(&self) -> WeightStats {
+        let mut wts: Vec<f64> = self.g.edge_weights().map(|e| e.wt).collect();
+        if wts.is_empty() {
+            return WeightStats::default();
+        }
+        wts.sort_by(f64::total_cmp);
+
+        let n = wts.len();
+        let mean = wts.iter().sum::<f64>() / n as f64;
+        let var = wts.iter().map(|w| (w - mean).powi(2)).sum::<f64>() / n as f64;
+        let median = if n % 2 == 1 {
+            wts[n / 2]
+        } else {
+            (wts[n / 2 - 1] + wts[n / 2]) / 2.0
+        };
+        WeightStats {
+            count: n,
+            min: wts[0],
+            max: wts[n - 1],
+            mean,
+            median,
+            std_dev: var.sqrt(),
+        }
+    }
+
+    pub fn remove_self_loops(&mut self) {
+        self.g.retain_edges(|g, e| {
+            let (u, v) = g.edge_endpoints(e).unwrap();
+            u != v
+        });
+        self.tree = None;
+    }
+
+    pub fn has_multi_edges(&self) -> bool {
+        let mut seen = HashSet::new();
+        self.g
+            .edge_references()
+            .any(|e| !seen.insert((e.source(), e.target())))
+    }
+
+    /// Copies the graph with each group of parallel edges (same endpoints and
+    /// direction) collapsed into one edge carrying the group's minimum weight.
+    pub fn to_simple(&self) -> Grf {
+        let mut res = self.copy_nodes();
+        for e in self.g.edge_references() {
+            match res.g.find_edge(e.source(), e.target()) {
+                Some(x) => res.g[x].wt = res.g[x].wt.min(e.weight().wt),
+                None => {
+                    res.g.add_edge(e.source(), e.target(), Ed { wt: e.weight().wt, flow: 0.0 });
+                }
+            }
+        }
+        res
+    }
+
+    /// Keeps each edge with probability `keep_fraction`, or, when `weighted`,
+    /// proportionally to its weight. Kept edges are reweighted by `1 / p` so
+    /// total weight is preserved in expectation.
+    pub fn sparsify(&self, keep_fraction: f64, seed: u64, weighted: bool) -> Grf {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let m = self.g.edge_count() as f64;
+        let total: f64 = self.g.edge_weights().map(|e| e.wt).sum();
+
+        let mut res = self.copy_nodes();
+        for e in self.g.edge_references() {
+            let p = if keep_fraction >= 1.0 {
+                1.0
+            } else if weighted && total > 0.0 {
+                (keep_fraction * m * e.weight().wt / total).min(1.0)
+            } else {
+                keep_fraction
+            };
+            if p > 0.0 && rng.gen::<f64>() < p {
+                res.g.add_edge(e.source(), e.target(), Ed { wt: e.weight().wt / p, flow: 0.0 });
+            }
+        }
+        res
+    }
+
+    pub fn structural_hash(&self) -> u64 {
+        let mut h = DefaultHasher::new();
+        self.canonical().hash(&mut h);
+        h.finish()
+    }
+
+    fn canonical(&self) -> Canonical {
+        let mut nds: Vec<_> = self
+            .g
+            .node_weights()
+            .map(|nd| (nd.id, nd.val.to_bits()))
+            .collect();
+        let mut eds: Vec<_> = self
+            .g
+            .edge_references()
+            .map(|e| (self.g[e.source()].id, self.g[e.target()].id, e.weight().wt.to_bits()))
+            .collect();
+        nds.sort_unstable();
+        eds.sort_unstable();
+        (nds, eds)
+    }
+
+    /// Counts in- and out-edges, so a self-loop adds 2 (or twice its weight).
+    pub fn degree_centrality(&self, weighted: bool) -> Vec<(usize, f64)> {
+        let norm = (self.g.node_count().max(2) - 1) as f64;
+        let mut res: Vec<(usize, f64)> = self
+            .g
+            .node_indices()
+            .map(|u| {
+                let deg: f64 = self
+                    .g
+                    .edges_directed(u, Direction::Outgoing)
+                    .chain(self.g.edges_directed(u, Direction::Incoming))
+                    .map(|e| if weighted { e.weight().wt } else { 1.0 })
+                    .sum();
+                (self.g[u].id, deg / norm)
+            })
+            .collect();
+        res.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        res
+    }
+
+    /// Pearson correlation of the degrees at either end of each edge, with
+    /// edges treated as undirected. Returns 0.0 when it is undefined, i.e.
+    /// all endpoints share one degree as in a regular graph.
+    pub fn degree_assortativity(&self) -> f64 {
+        let mut deg = vec![0.0; self.g.node_count()];
+        for e in self.g.edge_references() {
+            deg[e.source().index()] += 1.0;
+            deg[e.target().index()] += 1.0;
+        }
+
+        let (mut sum, mut sq, mut prod) = (0.0, 0.0, 0.0);
+        for e in self.g.edge_references() {
+            let (a, b) = (deg[e.source().index()], deg[e.target().index()]);
+            sum += a + b;
+            sq += a * a + b * b;
+            prod += 2.0 * a * b;
+        }
+
+        let m = 2.0 * self.g.edge_count() as f64;
+        if m == 0.0 {
+            return 0.0;
+        }
+        let mean = sum / m;
+        let var = sq / m - mean * mean;
+        if var <= f64::EPSILON {
+            return 0.0;
+        }
+        ((prod / m - mean * mean) / var).clamp(-1.0, 1.0)
+    }
+
+    pub fn betweenness_centrality(&self, weighted: bool, normalized: bool) -> HashMap<usize, f64> {
+        self.betweenness_centrality_cancellable(weighted, normalized, &AtomicBool::new(false))
+            .unwrap_or_default()
+    }
+
+    /// Like `betweenness_centrality`, checking `cancel` before each source
+    /// and returning `GraphError::Cancelled` once it is set.
+    pub fn betweenness_centrality_cancellable(
+        &self,
+        weighted: bool,
+        normalized: bool,
+        cancel: &AtomicBool,
+    ) -> Result<HashMap<usize, f64>, GraphError> {
+        let n = self.g.node_count();
+        let mut cb = vec![0.0; n];
+
+        for s in self.g.node_indices() {
+            if cancel.load(AtomicOrdering::Relaxed) {
+                return Err(GraphError::Cancelled);
+            }
+            let (mut stack, preds, sigma) = self.brandes_sssp(s, weighted);
+            let mut delta = vec![0.0; n];
+
+            while let Some(w) = stack.pop() {
+                for &v in &preds[w] {
+                    delta[v] += sigma[v] / sigma[w] * (1.0 + delta[w]);
+                }
+                if w != s.index() {
+                    cb[w] += delta[w];
+                }
+            }
+        }
+
+        let scale = if normalized && n > 2 {
+            1.0 / ((n - 1) * (n - 2)) as f64
+        } else {
+            1.0
+        };
+        Ok(self
+            .g
+            .node_indices()
+            .map(|u| (self.g[u].id, cb[u.index()] * scale))
+            .collect())
+    }
+
+    fn brandes_sssp(&self, s: NodeIndex, weighted: bool) -> (Vec<usize>, Vec<Vec<usize>>, Vec<f64>) {
+        let n = self.g.node_count();
+        let mut stack = Vec::new();
+        let mut preds = vec![Vec::new(); n];
+        let mut sigma = vec![0.0; n];
+        let mut dist = vec![f64::INFINITY; n];
+        sigma[s.index()] = 1.0;
+        dist[s.index()] = 0.0;
+
+        if weighted {
+            let mut done = vec![false; n];
+            let mut heap = BinaryHeap::new();
+            heap.push(Edge::new(s, s, -0.0));
+
+            while let Some(Edge { u, v, wt }) = heap.pop() {
+                if done[v.index()] {
+                    continue;
+                }
+                done[v.index()] = true;
+                if u != v {
+                    sigma[v.index()] += sigma[u.index()];
+                }
+                stack.push(v.index());
+
+                for e in self.g.edges(v) {
+                    let w = e.target().index();
+                    // A settled node, including `v` itself via a self-loop,
+                    // already has its final `sigma` and `preds`.
+                    if done[w] {
+                        continue;
+                    }
+                    let d = -wt + e.weight().wt;
+                    if d < dist[w] {
+                        dist[w] = d;
+                        sigma[w] = 0.0;
+                        preds[w] = vec![v.index()];
+                        heap.push(Edge::new(v, e.target(), -d));
+                    } else if d == dist[w] {
+                        sigma[w] += sigma[v.index()];
+                        preds[w].push(v.index());
+                    }
+                }
+            }
+        } else {
+            let mut queue = VecDeque::new();
+            queue.push_back(s);
+
+            while let Some(v) = queue.pop_front() {
+                stack.push(v.index());
+                for e in self.g.edges(v) {
+                    let w = e.target().index();
+                    if dist[w].is_infinite() {
+                        dist[w] = dist[v.index()] + 1.0;
+                        queue.push_back(e.target());
+                    }
+                    if dist[w] == dist[v.index()] + 1.0 {
+                        sigma[w] += sigma[v.index()];
+                        preds[w].push(v.index());
+                    }
+                }
+            }
+        }
+        (stack, preds, sigma)
+    }
+
+    /// Jaccard similarity of the out-neighbor sets of `a` and `b`, in `[0, 1]`.
+    /// When `weighted`, each neighbor counts with its edge weight (summed over
+    /// parallel edges) and the ratio is of summed minima to summed maxima.
+    pub fn neighbor_similarity(&self, a: usize, b: usize, weighted: bool) -> f64 {
+        let weights = |id: usize| {
+            let mut res: HashMap<usize, f64> = HashMap::new();
+            for (v, wt) in self.neighbors(id) {
+                let w = res.entry(v).or_default();
+                *w = if weighted { *w + wt } else { 1.0 };
+            }
+            res
+        };
+        let (wa, wb) = (weights(a), weights(b));
+
+        let (mut inter, mut union) = (0.0, 0.0);
+        for v in wa.keys().chain(wb.keys().filter(|v| !wa.contains_key(v))) {
+            let x = wa.get(v).copied().unwrap_or(0.0);
+            let y = wb.get(v).copied().unwrap_or(0.0);
+            inter += x.min(y);
+            union += x.max(y);
+        }
+        if union == 0.0 { 0.0 } else { inter / union }
+    }
+
+    /// Out-neighbors as one bitset per node, a word per 64 nodes, for dense
+    /// graphs where set intersection dominates.
+    pub fn to_bit_adjacency(&self) -> BitAdjacency {
+        let n = self.g.node_count();
+        let mut rows = vec![vec![0u64; n.div_ceil(64)]; n];
+        for e in self.g.edge_references() {
+            let v = e.target().index();
+            rows[e.source().index()][v / 64] |= 1 << (v % 64);
+        }
+        BitAdjacency {
+            ids: self.g.node_weights().map(|nd| nd.id).collect(),
+            pos: self.idx_map.iter().map(|(&id, u)| (id, u.index())).collect(),
+            rows,
+        }
+    }
+
+    /// Counts 3-cliques treating edges as undirected; direction, parallel
+    /// edges and self-loops are ignored.
+    pub fn count_triangles(&self) -> usize {
+        let n = self.g.node_count();
+        let mut adj = vec![HashSet::new(); n];
+        for e in self.g.edge_references() {
+            let (u, v) = (e.source().index(), e.target().index());
+            if u != v {
+                adj[u].insert(v);
+                adj[v].insert(u);
+            }
+        }
+
+        let rank = |u: usize| (adj[u].len(), u);
+        let fwd: Vec<HashSet<usize>> = (0..n)
+            .map(|u| adj[u].iter().copied().filter(|&v| rank(v) > rank(u)).collect())
+            .collect();
+
+        (0..n)
+            .map(|u| {
+                fwd[u]
+                    .iter()
+                    .map(|&v| fwd[u].intersection(&fwd[v]).count())
+                    .sum::<usize>()
+            })
+            .sum()
+    }
+
+    /// A maximal independent set, sorted by id, built by repeatedly taking
+    /// the node of least remaining degree. Edges are treated as undirected
+    /// and nodes with self-loops are never chosen. Not necessarily maximum.
+    pub fn max_independent_set_greedy(&self) -> Vec<usize> {
+        let n = self.g.node_count();
+        let mut adj = vec![HashSet::new(); n];
+        let mut alive = vec![true; n];
+        for e in self.g.edge_references() {
+            let (u, v) = (e.source().index(), e.target().index());
+            if u == v {
+                alive[u] = false;
+            } else {
+                adj[u].insert(v);
+                adj[v].insert(u);
+            }
+        }
+
+        let mut res = Vec::new();
+        while let Some(u) = (0..n)
+            .filter(|&u| alive[u])
+            .min_by_key(|&u| adj[u].iter().filter(|&&v| alive[v]).count())
+        {
+            alive[u] = false;
+            for &v in &adj[u] {
+                alive[v] = false;
+            }
+            res.push(self.g[NodeIndex::new(u)].id);
+        }
+        res.sort_unstable();
+        res
+    }
+
+    /// Self-loops never join the tree, here or in the other spanning tree
+    /// methods, since they connect no new node.
+    pub fn mst(&self) -> Vec<(usize, usize, f64)> {
+        let mut res = Vec::new();
+        let mut seen = HashSet::new();
+
+        if let Some(start) = self.g.node_indices().next() {
+            self.prim(start, &mut seen, &mut res);
+        }
+        res
+    }
+
+    /// Kruskal's algorithm with edges treated as undirected, giving a minimum
+    /// spanning tree for each weakly connected component.
+    ///
+    /// This is not `mst` extended to disconnected graphs: `mst` runs Prim
+    /// from the first node along outgoing edges only, so it covers just the
+    /// nodes reachable from there. The two agree, up to ties and edge order,
+    /// when every edge has a reverse of the same weight and the graph is
+    /// connected; on other directed graphs the forest may use edges `mst`
+    /// cannot follow.
+    pub fn minimum_spanning_forest(&self) -> Vec<(usize, usize, f64)> {
+        let mut eds: Vec<_> = self.g.edge_references().collect();
+        eds.sort_by(|a, b| a.weight().wt.total_cmp(&b.weight().wt));
+
+        let mut uf = UnionFind::new(self.g.node_count());
+        eds.into_iter()
+            .filter(|e| uf.union(e.source().index(), e.target().index()))
+            .map(|e| (self.g[e.source()].id, self.g[e.target()].id, e.weight().wt))
+            .collect()
+    }
+
+    /// Kruskal's algorithm with the `required` edges placed first and the
+    /// `forbidden` ones skipped. Edges are treated as undirected, so a pair
+    /// matches either direction. `None` if a required edge is missing or
+    /// closes a cycle, or the allowed edges do not span the graph.
+    pub fn mst_constrained(
+        &self,
+        required: &[(usize, usize)],
+        forbidden: &[(usize, usize)],
+    ) -> Option<Vec<(usize, usize, f64)>> {
+        let key = |a: NodeIndex, b: NodeIndex| (a.min(b), a.max(b));
+        let forbidden: HashSet<_> = forbidden
+            .iter()
+            .filter_map(|(a, b)| Some(key(*self.idx_map.get(a)?, *self.idx_map.get(b)?)))
+            .collect();
+
+        let mut eds: Vec<_> = self
+            .g
+            .edge_references()
+            .filter(|e| !forbidden.contains(&key(e.source(), e.target())))
+            .map(|e| (e.source(), e.target(), e.weight().wt))
+            .collect();
+        eds.sort_by(|a, b| a.2.total_cmp(&b.2));
+
+        let mut uf = UnionFind::new(self.g.node_count());
+        let mut res = Vec::new();
+        for (a, b) in required {
+            let pair = key(*self.idx_map.get(a)?, *self.idx_map.get(b)?);
+            let &(u, v, wt) = eds.iter().find(|&&(u, v, _)| key(u, v) == pair)?;
+            if !uf.union(u.index(), v.index()) {
+                return None;
+            }
+            res.push((self.g[u].id, self.g[v].id, wt));
+        }
+
+        for (u, v, wt) in eds {
+            if uf.union(u.index(), v.index()) {
+                res.push((self.g[u].id, self.g[v].id, wt));
+            }
+        }
+        (res.len() == self.g.node_count().saturating_sub(1)).then_some(res)
+    }
+
+    /// Adds the edge and updates the spanning forest maintained across calls
+    /// (computed on first use, discarded by `add_ed`). If the edge closes a
+    /// cycle whose heaviest tree edge outweighs it, that edge is swapped out
+    /// and returned; otherwise the tree is unchanged or simply extended.
+    /// Edges are undirected here, as in `minimum_spanning_forest`, so an
+    /// edge parallel to a lighter tree edge in either direction is rejected.
+    /// Fails like `add_ed` once the edge limit is reached.
+    pub fn mst_add_edge(&mut self, from: usize, to: usize, wt: f64) -> Result<Option<(usize, usize, f64)>, GraphError> {
+        let mut tree = match self.tree.take() {
+            Some(tree) => tree,
+            None => self.minimum_spanning_forest(),
+        };
+        self.add_ed(from, to, wt)?;
+
+        let removed = match Self::tree_path(&tree, from, to) {
+            Some(path) => {
+                let heaviest = path
+                    .into_iter()
+                    .max_by(|&a, &b| tree[a].2.total_cmp(&tree[b].2))
+                    .filter(|&i| tree[i].2 > wt);
+                heaviest.map(|i| {
+                    let old = tree.swap_remove(i);
+                    tree.push((from, to, wt));
+                    old
+                })
+            }
+            None => {
+                tree.push((from, to, wt));
+                None
+            }
+        };
+        self.tree = Some(tree);
+        Ok(removed)
+    }
+
+    fn tree_path(tree: &[(usize, usize, f64)], from: usize, to: usize) -> Option<Vec<usize>> {
+        let mut adj: HashMap<usize, Vec<(usize, usize)>> = HashMap::new();
+        for (i, &(u, v, _)) in tree.iter().enumerate() {
+            adj.entry(u).or_default().push((v, i));
+            adj.entry(v).or_default().push((u, i));
+        }
+
+        let mut prev = HashMap::new();
+        let mut queue = VecDeque::new();
+        prev.insert(from, None);
+        queue.push_back(from);
+        while let Some(u) = queue.pop_front() {
+            if u == to {
+                let mut path = Vec::new();
+                let mut curr = to;
+                while let Some(&Some((p, e))) = prev.get(&curr) {
+                    path.push(e);
+                    curr = p;
+                }
+                return Some(path);
+            }
+            for &(v, e) in adj.get(&u).into_iter().flatten() {
+                if let Entry::Vacant(slot) = prev.entry(v) {
+                    slot.insert(Some((u, e)));
+                    queue.push_back(v);
+                }
+            }
+        }
+        None
+    }
+
+    fn prim(
+        &self,
+        start: NodeIndex,
+        seen: &mut HashSet<NodeIndex>,
+        res: &mut Vec<(usize, usize, f64)>,
+    ) {
+        let mut heap = BinaryHeap::new();
+
+        seen.insert(start);
+        for e in self.g.edges(start) {
+            heap.push(Edge::new(start, e.target(), -e.weight().wt));
+        }
+
+        while let Some(Edge { u, v, wt }) = heap.pop() {
+            if seen.contains(&v) {
+                continue;
+            }
+
+            seen.insert(v);
+            res.push((
+                self.g[u].id,
+                self.g[v].id,
+                -wt
+            ));
+
+            for e in self.g.edges(v) {
+                if !seen.contains(&e.target()) {
+                    heap.push(Edge::new(v, e.target(), -e.weight().wt));
+                }
+            }
+        }
+    }
+
+    /// Self-loops are ignored; they cannot shorten a path.
+    pub fn shortest_path_tree(&self, from: usize) -> HashMap<usize, (f64, Option<usize>)> {
+        let mut res = HashMap::new();
+        let mut heap = BinaryHeap::new();
+        let src = self.idx_map[&from];
+        heap.push(Edge::new(src, src, -0.0));
+
+        while let Some(Edge { u, v, wt }) = heap.pop() {
+            let id = self.g[v].id;
+            if res.contains_key(&id) {
+                continue;
+            }
+
+            let pred = if u == v { None } else { Some(self.g[u].id) };
+            res.insert(id, (-wt, pred));
+
+            for e in self.g.edges(v) {
+                if !res.contains_key(&self.g[e.target()].id) {
+                    heap.push(Edge::new(v, e.target(), wt - e.weight().wt));
+                }
+            }
+        }
+        res
+    }
+
+    /// Greatest shortest-path distance from `id` along edge directions, or
+    /// infinity if some node cannot be reached.
+    pub fn eccentricity(&self, id: usize) -> f64 {
+        let tree = self.shortest_path_tree(id);
+        if tree.len() < self.g.node_count() {
+            return f64::INFINITY;
+        }
+        tree.values().map(|&(d, _)| d).fold(0.0, f64::max)
+    }
+
+    /// Estimates the greatest finite shortest-path distance by double sweeps
+    /// from `samples` random start nodes: search forward to the farthest node,
+    /// then backward from it. Each sweep measures a real distance, so the
+    /// result is a lower bound on the diameter. It is exact on paths and on
+    /// trees made undirected with `as_undirected`, but may fall short
+    /// elsewhere. 0 for an empty graph.
+    pub fn diameter_approx(&self, samples: usize, seed: u64) -> f64 {
+        let n = self.g.node_count();
+        if n == 0 {
+            return 0.0;
+        }
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut best = 0.0;
+        for _ in 0..samples {
+            let start = NodeIndex::new(rng.gen_range(0..n));
+            let (far, d1) = self.sweep(start, Direction::Outgoing);
+            let (_, d2) = self.sweep(far, Direction::Incoming);
+            best = f64::max(best, d1.max(d2));
+        }
+        best
+    }
+
+    /// The node farthest from `src` along edges in `dir`, and its distance.
+    fn sweep(&self, src: NodeIndex, dir: Direction) -> (NodeIndex, f64) {
+        let mut seen = HashSet::new();
+        let mut far = (src, 0.0);
+        let mut heap = BinaryHeap::new();
+        heap.push(Edge::new(src, src, -0.0));
+
+        while let Some(Edge { v, wt, .. }) = heap.pop() {
+            if !seen.insert(v) {
+                continue;
+            }
+            if -wt > far.1 {
+                far = (v, -wt);
+            }
+            for e in self.g.edges_directed(v, dir) {
+                let other = if dir == Direction::Outgoing { e.target() } else { e.source() };
+                if !seen.contains(&other) {
+                    heap.push(Edge::new(v, other, wt - e.weight().wt));
+                }
+            }
+        }
+        far
+    }
+
+    /// Nodes of minimum eccentricity, sorted by id. Empty when no node
+    /// reaches every other one, e.g. in a disconnected graph.
+    pub fn center(&self) -> Vec<usize> {
+        self.center_cancellable(&AtomicBool::new(false)).unwrap_or_default()
+    }
+
+    /// Like `center`, checking `cancel` before each eccentricity.
+    pub fn center_cancellable(&self, cancel: &AtomicBool) -> Result<Vec<usize>, GraphError> {
+        let mut ecc = Vec::new();
+        for nd in self.g.node_weights() {
+            if cancel.load(AtomicOrdering::Relaxed) {
+                return Err(GraphError::Cancelled);
+            }
+            ecc.push((nd.id, self.eccentricity(nd.id)));
+        }
+        let min = ecc.iter().map(|e| e.1).fold(f64::INFINITY, f64::min);
+        if min.is_infinite() {
+            return Ok(Vec::new());
+        }
+
+        let mut res: Vec<usize> = ecc.into_iter().filter(|e| e.1 == min).map(|e| e.0).collect();
+        res.sort_unstable();
+        Ok(res)
+    }
+
+    pub fn layout_force_directed(&mut self, iterations: usize) {
+        let n = self.g.node_count();
+        if n < 2 || iterations == 0 {
+            return;
+        }
+
+        let (mut lo, mut hi) = (self.g[NodeIndex::new(0)].pos, self.g[NodeIndex::new(0)].pos);
+        for nd in self.g.node_weights() {
+            lo = Complex64::new(lo.re.min(nd.pos.re), lo.im.min(nd.pos.im));
+            hi = Complex64::new(hi.re.max(nd.pos.re), hi.im.max(nd.pos.im));
+        }
+        let width = (hi.re - lo.re).max(hi.im - lo.im).max(1.0);
+        let k = width / (n as f64).sqrt();
+        let mut temp = width / 10.0;
+        let cool = temp / iterations as f64;
+
+        for _ in 0..iterations {
+            let mut disp = vec![Complex64::new(0.0, 0.0); n];
 
-pub struct Grf {
-    g: Graph<Nd, Ed>,
-    idx_map: HashMap<usize, NodeIndex>,
-}
+            for u in self.g.node_indices() {
+                for v in self.g.node_indices() {
+                    if u == v {
+                        continue;
+                    }
+                    let mut d = self.g[u].pos - self.g[v].pos;
+                    if d.norm() < 1e-9 {
+                        d = Complex64::from_polar(1e-3 * k, u.index() as f64);
+                    }
+                    let dist = d.norm();
+                    disp[u.index()] += d / dist * (k * k / dist);
+                }
+            }
 
-impl Grf {
-    pub fn new() -> Self {
-        Self {
-            g: Graph::new(),
-            idx_map: HashMap::new(),
+            for e in self.g.edge_references() {
+                let (u, v) = (e.source(), e.target());
+                let d = self.g[u].pos - self.g[v].pos;
+                let dist = d.norm();
+                if dist < 1e-9 {
+                    continue;
+                }
+                let pull = d / dist * (dist * dist / (k * e.weight().wt.max(f64::EPSILON)));
+                disp[u.index()] -= pull;
+                disp[v.index()] += pull;
+            }
+
+            for u in self.g.node_indices() {
+                let d = disp[u.index()];
+                let len = d.norm();
+                if len > 0.0 {
+                    self.g[u].pos += d / len * len.min(temp);
+                }
+            }
+            temp -= cool;
         }
     }
 
-    pub fn add_nd(&mut self, id: usize, val: f64, x: f64, y: f64) -> NodeIndex {
-        let nd = Nd {
-            id,
-            val,
-            pos: Complex64::new(x, y),
-        };
-        let idx = self.g.add_node(nd);
-        self.idx_map.insert(id, idx);
-        idx
+    /// Treats every edge as undirected. Returns `None` if the edges do not
+    /// form a single connected component, or if more than
+    /// `POSTMAN_MAX_ODD` nodes have odd degree: they are paired by a search
+    /// whose time and memory double with each one.
+    pub fn chinese_postman(&self) -> Option<(f64, Vec<usize>)> {
+        let n = self.g.node_count();
+        let mut eds: Vec<(usize, usize, f64)> = self
+            .g
+            .edge_references()
+            .map(|e| (e.source().index(), e.target().index(), e.weight().wt))
+            .collect();
+        if eds.is_empty() {
+            return Some((0.0, Vec::new()));
+        }
+
+        let mut adj = vec![Vec::new(); n];
+        for (i, &(u, v, _)) in eds.iter().enumerate() {
+            adj[u].push((v, i));
+            adj[v].push((u, i));
+        }
+
+        let start = eds[0].0;
+        let (dist, _) = Self::dijkstra_undirected(&adj, &eds, start);
+        if (0..n).any(|u| !adj[u].is_empty() && dist[u].is_infinite()) {
+            return None;
+        }
+
+        let odd: Vec<usize> = (0..n).filter(|&u| adj[u].len() % 2 == 1).collect();
+        if odd.len() > POSTMAN_MAX_ODD {
+            return None;
+        }
+        let sp: Vec<_> = odd
+            .iter()
+            .map(|&u| Self::dijkstra_undirected(&adj, &eds, u))
+            .collect();
+
+        let k = odd.len();
+        let full = (1usize << k) - 1;
+        let mut best = vec![f64::INFINITY; full + 1];
+        let mut pair = vec![(0, 0); full + 1];
+        best[0] = 0.0;
+        for mask in 0..full {
+            if best[mask].is_infinite() {
+                continue;
+            }
+            let i = (!mask).trailing_zeros() as usize;
+            for (j, &v) in odd.iter().enumerate().skip(i + 1) {
+                if mask & (1 << j) != 0 {
+                    continue;
+                }
+                let next = mask | (1 << i) | (1 << j);
+                let cost = best[mask] + sp[i].0[v];
+                if cost < best[next] {
+                    best[next] = cost;
+                    pair[next] = (i, j);
+                }
+            }
+        }
+
+        let mut extra = Vec::new();
+        let mut mask = full;
+        while mask != 0 {
+            let (i, j) = pair[mask];
+            let mut curr = odd[j];
+            while let Some((p, e)) = sp[i].1[curr] {
+                extra.push(e);
+                curr = p;
+            }
+            mask &= !((1 << i) | (1 << j));
+        }
+        for e in extra {
+            let (u, v, _) = eds[e];
+            adj[u].push((v, eds.len()));
+            adj[v].push((u, eds.len()));
+            eds.push(eds[e]);
+        }
+
+        let mut used = vec![false; eds.len()];
+        let mut next = vec![0; n];
+        let mut stack = vec![start];
+        let mut walk = Vec::new();
+        while let Some(&u) = stack.last() {
+            while next[u] < adj[u].len() && used[adj[u][next[u]].1] {
+                next[u] += 1;
+            }
+            if next[u] == adj[u].len() {
+                walk.push(self.g[NodeIndex::new(u)].id);
+                stack.pop();
+            } else {
+                let (v, e) = adj[u][next[u]];
+                used[e] = true;
+                stack.push(v);
+            }
+        }
+        walk.reverse();
+
+        Some((eds.iter().map(|e| e.2).sum(), walk))
     }
 
-    pub fn add_ed(&mut self, from: usize, to: usize, wt: f64) {
-        let u = self.idx_map[&from];
-        let v = self.idx_map[&to];
-        self.g.add_edge(u, v, Ed { wt, flow: 0.0 });
+    fn dijkstra_undirected(
+        adj: &[Vec<(usize, usize)>],
+        eds: &[(usize, usize, f64)],
+        src: usize,
+    ) -> (Vec<f64>, Vec<Option<(usize, usize)>>) {
+        let mut dist = vec![f64::INFINITY; adj.len()];
+        let mut prev = vec![None; adj.len()];
+        let mut heap = BinaryHeap::new();
+        dist[src] = 0.0;
+        heap.push(Edge::new(NodeIndex::new(src), NodeIndex::new(src), -0.0));
+
+        while let Some(Edge { v, wt, .. }) = heap.pop() {
+            let u = v.index();
+            if -wt > dist[u] {
+                continue;
+            }
+            for &(v, e) in &adj[u] {
+                let d = dist[u] + eds[e].2;
+                if d < dist[v] {
+                    dist[v] = d;
+                    prev[v] = Some((u, e));
+                    heap.push(Edge::new(NodeIndex::new(u), NodeIndex::new(v), -d));
+                }
+            }
+        }
+        (dist, prev)
     }
 
-    pub fn mst(&self) -> Vec<(usize, usize, f64)> {
-        let mut res = Vec::new();
-        let mut seen = HashSet::new();
+    /// Contracts nodes in order of increasing in-degree times out-degree,
+    /// adding shortcut edges that keep distances between the remaining nodes,
+    /// so queries only search upward in that order. Assumes non-negative
+    /// weights; later changes to the graph are not reflected.
+    pub fn build_contraction_hierarchy(&self) -> ContractionHierarchy {
+        let n = self.g.node_count();
+        let mut out: Vec<HashMap<usize, f64>> = vec![HashMap::new(); n];
+        let mut inn: Vec<HashMap<usize, f64>> = vec![HashMap::new(); n];
+        for e in self.g.edge_references() {
+            let (u, v) = (e.source().index(), e.target().index());
+            if u != v {
+                let w = out[u].get(&v).map_or(e.weight().wt, |&w| w.min(e.weight().wt));
+                out[u].insert(v, w);
+                inn[v].insert(u, w);
+            }
+        }
+
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by_key(|&v| (out[v].len() * inn[v].len(), v));
+
+        let mut up = vec![Vec::new(); n];
+        let mut down = vec![Vec::new(); n];
+        for v in order {
+            let ins: Vec<(usize, f64)> = inn[v].iter().map(|(&u, &w)| (u, w)).collect();
+            let outs: Vec<(usize, f64)> = out[v].iter().map(|(&x, &w)| (x, w)).collect();
+            for &(u, w1) in &ins {
+                let limit = outs.iter().map(|&(_, w2)| w1 + w2).fold(0.0, f64::max);
+                let dist = Self::witness(&out, u, v, limit);
+                for &(x, w2) in &outs {
+                    let via = w1 + w2;
+                    if x == u || dist.get(&x).is_some_and(|&d| d <= via) {
+                        continue;
+                    }
+                    if let Some(&w) = out[u].get(&x) {
+                        if w <= via {
+                            continue;
+                        }
+                    }
+                    out[u].insert(x, via);
+                    inn[x].insert(u, via);
+                }
+            }
+
+            // Everything still attached to v is ranked above it.
+            for (&x, &w) in &out[v] {
+                up[v].push((x, w));
+                inn[x].remove(&v);
+            }
+            for (&u, &w) in &inn[v] {
+                down[v].push((u, w));
+                out[u].remove(&v);
+            }
+            out[v].clear();
+            inn[v].clear();
+        }
+
+        ContractionHierarchy {
+            idx: self.idx_map.iter().map(|(&id, u)| (id, u.index())).collect(),
+            up,
+            down,
+        }
+    }
+
+    /// Distances from `src` avoiding `skip`, up to `limit` or a fixed number
+    /// of settled nodes; a path it misses only costs an extra shortcut.
+    fn witness(out: &[HashMap<usize, f64>], src: usize, skip: usize, limit: f64) -> HashMap<usize, f64> {
+        let mut dist = HashMap::new();
         let mut heap = BinaryHeap::new();
+        heap.push(Edge::new(NodeIndex::new(src), NodeIndex::new(src), -0.0));
 
-        if let Some(start) = self.g.node_indices().next() {
-            seen.insert(start);
-            for e in self.g.edges(start) {
-                heap.push(Edge::new(start, e.target(), -e.weight().wt));
+        while let Some(Edge { v, wt, .. }) = heap.pop() {
+            if -wt > limit || dist.len() >= 500 {
+                break;
+            }
+            match dist.entry(v.index()) {
+                Entry::Vacant(slot) => slot.insert(-wt),
+                Entry::Occupied(_) => continue,
+            };
+            for (&x, &w) in &out[v.index()] {
+                if x != skip && !dist.contains_key(&x) {
+                    heap.push(Edge::new(v, NodeIndex::new(x), wt - w));
+                }
             }
+        }
+        dist
+    }
 
-            while let Some(Edge { u, v, wt }) = heap.pop() {
-                if seen.contains(&v) {
-                    continue;
+    pub fn shortest_path_unweighted(&self, from: usize, to: usize) -> Option<Vec<usize>> {
+        let s = self.idx_map[&from];
+        let t = self.idx_map[&to];
+        let mut prev = HashMap::new();
+        let mut queue = VecDeque::new();
+        prev.insert(s, s);
+        queue.push_back(s);
+
+        while let Some(u) = queue.pop_front() {
+            if u == t {
+                let mut path = vec![self.g[t].id];
+                let mut curr = t;
+                while curr != s {
+                    curr = prev[&curr];
+                    path.push(self.g[curr].id);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for v in self.g.neighbors(u) {
+                if let Entry::Vacant(slot) = prev.entry(v) {
+                    slot.insert(u);
+                    queue.push_back(v);
                 }
+            }
+        }
+        None
+    }
 
-                seen.insert(v);
-                res.push((
-                    self.g[u].id,
-                    self.g[v].id,
-                    -wt
-                ));
+    /// Heaviest path from `from` to `to`, relaxing edges in topological
+    /// order. `None` if the graph has a cycle or `to` is unreachable.
+    pub fn longest_path_dag(&self, from: usize, to: usize) -> Option<(f64, Vec<usize>)> {
+        let order = self.topo_order()?;
+        let s = self.idx_map[&from];
+        let t = self.idx_map[&to];
 
-                for e in self.g.edges(v) {
-                    if !seen.contains(&e.target()) {
-                        heap.push(Edge::new(v, e.target(), -e.weight().wt));
+        let mut dist = vec![f64::NEG_INFINITY; self.g.node_count()];
+        let mut prev = vec![None; self.g.node_count()];
+        dist[s.index()] = 0.0;
+        for u in order {
+            if dist[u.index()].is_infinite() {
+                continue;
+            }
+            for e in self.g.edges(u) {
+                let d = dist[u.index()] + e.weight().wt;
+                if d > dist[e.target().index()] {
+                    dist[e.target().index()] = d;
+                    prev[e.target().index()] = Some(u);
+                }
+            }
+        }
+        if dist[t.index()].is_infinite() {
+            return None;
+        }
+
+        let mut path = vec![self.g[t].id];
+        let mut curr = t;
+        while let Some(p) = prev[curr.index()] {
+            path.push(self.g[p].id);
+            curr = p;
+        }
+        path.reverse();
+        Some((dist[t.index()], path))
+    }
+
+    /// Groups nodes by the length of the longest path reaching them from any
+    /// source, so every edge points to a later layer. Ids are sorted within
+    /// each layer.
+    pub fn topo_layers(&self) -> Result<Vec<Vec<usize>>, GraphError> {
+        let order = self.topo_order().ok_or(GraphError::Cycle)?;
+        let mut depth = vec![0; self.g.node_count()];
+        let mut layers: Vec<Vec<usize>> = Vec::new();
+        for u in order {
+            let d = depth[u.index()];
+            for v in self.g.neighbors(u) {
+                depth[v.index()] = depth[v.index()].max(d + 1);
+            }
+            if layers.len() <= d {
+                layers.resize_with(d + 1, Vec::new);
+            }
+            layers[d].push(self.g[u].id);
+        }
+        for layer in &mut layers {
+            layer.sort_unstable();
+        }
+        Ok(layers)
+    }
+
+    /// Kahn's algorithm; `None` if the graph has a cycle.
+    fn topo_order(&self) -> Option<Vec<NodeIndex>> {
+        let mut indeg: Vec<usize> = self
+            .g
+            .node_indices()
+            .map(|u| self.g.edges_directed(u, Direction::Incoming).count())
+            .collect();
+        let mut queue: VecDeque<NodeIndex> =
+            self.g.node_indices().filter(|u| indeg[u.index()] == 0).collect();
+
+        let mut order = Vec::new();
+        while let Some(u) = queue.pop_front() {
+            order.push(u);
+            for v in self.g.neighbors(u) {
+                indeg[v.index()] -= 1;
+                if indeg[v.index()] == 0 {
+                    queue.push_back(v);
+                }
+            }
+        }
+        (order.len() == self.g.node_count()).then_some(order)
+    }
+
+    /// Flow on each edge as left by the last `max_flow` run.
+    pub fn edge_flows(&self) -> Vec<(usize, usize, f64)> {
+        self.g
+            .edge_references()
+            .map(|e| (self.g[e.source()].id, self.g[e.target()].id, e.weight().flow))
+            .collect()
+    }
+
+    /// Depth-first traversal from `start` following edge directions. Edges
+    /// to already finished nodes (forward and cross edges) are not reported.
+    pub fn dfs_visit<F: FnMut(usize, VisitEvent)>(&self, start: usize, mut visitor: F) {
+        let s = self.idx_map[&start];
+        let mut discovered = vec![false; self.g.node_count()];
+        let mut finished = vec![false; self.g.node_count()];
+
+        discovered[s.index()] = true;
+        visitor(start, VisitEvent::Discover);
+        let mut stack = vec![(s, self.g.neighbors(s))];
+        while let Some((u, iter)) = stack.last_mut() {
+            let u = *u;
+            match iter.next() {
+                Some(v) if !discovered[v.index()] => {
+                    discovered[v.index()] = true;
+                    visitor(self.g[u].id, VisitEvent::TreeEdge(self.g[v].id));
+                    visitor(self.g[v].id, VisitEvent::Discover);
+                    stack.push((v, self.g.neighbors(v)));
+                }
+                Some(v) if !finished[v.index()] => {
+                    visitor(self.g[u].id, VisitEvent::BackEdge(self.g[v].id));
+                }
+                Some(_) => {}
+                None => {
+                    finished[u.index()] = true;
+                    visitor(self.g[u].id, VisitEvent::Finish);
+                    stack.pop();
+                }
+            }
+        }
+    }
+
+    pub fn reset_flow(&mut self) {
+        for e in self.g.edge_weights_mut() {
+            e.flow = 0.0;
+        }
+    }
+
+    /// Stoer-Wagner over the undirected view of the graph; parallel and
+    /// antiparallel edge weights are summed. Returns the cut weight and the
+    /// ids on one side of it.
+    pub fn global_min_cut(&self) -> (f64, Vec<usize>) {
+        let n = self.g.node_count();
+        if n < 2 {
+            return (0.0, Vec::new());
+        }
+
+        let mut w = vec![vec![0.0; n]; n];
+        for e in self.g.edge_references() {
+            let (u, v) = (e.source().index(), e.target().index());
+            if u != v {
+                w[u][v] += e.weight().wt;
+                w[v][u] += e.weight().wt;
+            }
+        }
+
+        let mut groups: Vec<Vec<usize>> = (0..n).map(|u| vec![u]).collect();
+        let mut active: Vec<usize> = (0..n).collect();
+        let mut best = (f64::INFINITY, Vec::new());
+
+        while active.len() > 1 {
+            let mut key = vec![0.0f64; n];
+            let mut added = vec![false; n];
+            let (mut prev, mut last) = (active[0], active[0]);
+
+            for _ in 0..active.len() {
+                let u = active
+                    .iter()
+                    .copied()
+                    .filter(|&v| !added[v])
+                    .max_by(|&a, &b| key[a].total_cmp(&key[b]))
+                    .unwrap();
+                added[u] = true;
+                prev = last;
+                last = u;
+                for &v in &active {
+                    if !added[v] {
+                        key[v] += w[u][v];
                     }
                 }
             }
+
+            if key[last] < best.0 {
+                best = (key[last], groups[last].clone());
+            }
+
+            let moved = std::mem::take(&mut groups[last]);
+            groups[prev].extend(moved);
+            for &v in &active {
+                w[prev][v] += w[last][v];
+                w[v][prev] = w[prev][v];
+            }
+            active.retain(|&v| v != last);
         }
-        res
+
+        let mut side: Vec<usize> = best
+            .1
+            .into_iter()
+            .map(|u| self.g[NodeIndex::new(u)].id)
+            .collect();
+        side.sort_unstable();
+        (best.0, side)
     }
 
     pub fn max_flow(&mut self, s: usize, t: usize) -> f64 {
+        self.reset_flow();
         let source = self.idx_map[&s];
         let sink = self.idx_map[&t];
         let mut flow = 0.0;
@@ -111,6 +1578,148 @@ impl Grf {
         flow
     }
 
+    /// Capacity scaling: only paths whose arcs all have at least `delta`
+    /// spare capacity are augmented, halving `delta` down to 1 and finishing
+    /// with a pass over any spare capacity. Flow can be pushed back along
+    /// used edges. Edge flows are left set as by `max_flow`.
+    pub fn max_flow_scaling(&mut self, s: usize, t: usize) -> f64 {
+        self.reset_flow();
+        let src = self.idx_map[&s].index();
+        let sink = self.idx_map[&t].index();
+
+        // Arc 2i is edge i and arc 2i + 1 its reverse, whose spare capacity
+        // is the flow on edge i.
+        let mut to = Vec::new();
+        let mut cap = Vec::new();
+        let mut adj = vec![Vec::new(); self.g.node_count()];
+        for e in self.g.edge_references() {
+            let (u, v) = (e.source().index(), e.target().index());
+            adj[u].push(to.len());
+            to.push(v);
+            cap.push(e.weight().wt.max(0.0));
+            adj[v].push(to.len());
+            to.push(u);
+            cap.push(0.0);
+        }
+
+        let max = cap.iter().copied().fold(0.0, f64::max);
+        let mut delta = if max >= 1.0 { max.log2().floor().exp2() } else { 0.0 };
+        let mut flow = 0.0;
+        loop {
+            while let Some(path) = Self::residual_path(&adj, &to, &cap, src, sink, delta) {
+                let push = path.iter().map(|&a| cap[a]).fold(f64::INFINITY, f64::min);
+                for &a in &path {
+                    cap[a] -= push;
+                    cap[a ^ 1] += push;
+                }
+                flow += push;
+            }
+            if delta == 0.0 {
+                break;
+            }
+            delta = if delta > 1.0 { delta / 2.0 } else { 0.0 };
+        }
+
+        for (i, e) in self.g.edge_weights_mut().enumerate() {
+            e.flow = cap[2 * i + 1];
+        }
+        flow
+    }
+
+    /// A largest set of paths from `from` to `to` sharing no edge, found as
+    /// a unit-capacity max flow, so weights are ignored. Their number is the
+    /// fewest edges whose removal cuts `to` off from `from`. Paths are node
+    /// ids in order; empty when `from == to` or either id is unknown.
+    pub fn edge_disjoint_paths(&self, from: usize, to: usize) -> Vec<Vec<usize>> {
+        let (Some(&s), Some(&t)) = (self.idx_map.get(&from), self.idx_map.get(&to)) else {
+            return Vec::new();
+        };
+        let (src, sink) = (s.index(), t.index());
+        if src == sink {
+            return Vec::new();
+        }
+
+        let mut head = Vec::new();
+        let mut cap = Vec::new();
+        let mut adj = vec![Vec::new(); self.g.node_count()];
+        for e in self.g.edge_references() {
+            let (u, v) = (e.source().index(), e.target().index());
+            adj[u].push(head.len());
+            head.push(v);
+            cap.push(if u == v { 0.0 } else { 1.0 });
+            adj[v].push(head.len());
+            head.push(u);
+            cap.push(0.0);
+        }
+
+        let mut count = 0;
+        while let Some(path) = Self::residual_path(&adj, &head, &cap, src, sink, 0.0) {
+            for &a in &path {
+                cap[a] -= 1.0;
+                cap[a ^ 1] += 1.0;
+            }
+            count += 1;
+        }
+
+        // Walk the edges carrying flow, cutting out any loop the walk makes.
+        let mut used = vec![Vec::new(); adj.len()];
+        for a in (0..head.len()).step_by(2) {
+            if cap[a + 1] > 0.0 {
+                used[head[a + 1]].push(head[a]);
+            }
+        }
+        (0..count)
+            .map(|_| {
+                let mut path = vec![src];
+                let mut curr = src;
+                while curr != sink {
+                    curr = used[curr].pop().expect("flow is conserved");
+                    match path.iter().position(|&x| x == curr) {
+                        Some(i) => path.truncate(i + 1),
+                        None => path.push(curr),
+                    }
+                }
+                path.into_iter().map(|i| self.g[NodeIndex::new(i)].id).collect()
+            })
+            .collect()
+    }
+
+    fn residual_path(
+        adj: &[Vec<usize>],
+        to: &[usize],
+        cap: &[f64],
+        src: usize,
+        sink: usize,
+        delta: f64,
+    ) -> Option<Vec<usize>> {
+        let mut prev = vec![None; adj.len()];
+        let mut seen = vec![false; adj.len()];
+        let mut queue = VecDeque::new();
+        seen[src] = true;
+        queue.push_back(src);
+
+        while let Some(u) = queue.pop_front() {
+            if u == sink {
+                let mut path = Vec::new();
+                let mut curr = sink;
+                while let Some(a) = prev[curr] {
+                    path.push(a);
+                    curr = to[a ^ 1];
+                }
+                return (!path.is_empty()).then_some(path);
+            }
+            for &a in &adj[u] {
+                let v = to[a];
+                if !seen[v] && cap[a] > 0.0 && cap[a] >= delta {
+                    seen[v] = true;
+                    prev[v] = Some(a);
+                    queue.push_back(v);
+                }
+            }
+        }
+        None
+    }
+
     fn find_path(&self, s: NodeIndex, t: NodeIndex) -> Vec<NodeIndex> {
         let mut seen = HashSet::new();
         let mut queue = VecDeque::new();
@@ -145,6 +1754,161 @@ impl Grf {
     }
 }
 
+impl Default for Grf {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PartialEq for Grf {
+    fn eq(&self, other: &Self) -> bool {
+        self.canonical() == other.canonical()
+    }
+}
+
+/// Shortest-path index built by `Grf::build_contraction_hierarchy`.
+pub struct ContractionHierarchy {
+    idx: HashMap<usize, usize>,
+    up: Vec<Vec<(usize, f64)>>,
+    down: Vec<Vec<(usize, f64)>>,
+}
+
+impl ContractionHierarchy {
+    /// Shortest distance from `from` to `to`; `None` if unreachable or either
+    /// id is unknown.
+    pub fn query(&self, from: usize, to: usize) -> Option<f64> {
+        let fwd = Self::search(&self.up, *self.idx.get(&from)?);
+        let bwd = Self::search(&self.down, *self.idx.get(&to)?);
+        let best = fwd
+            .iter()
+            .filter_map(|(v, d)| Some(d + bwd.get(v)?))
+            .fold(f64::INFINITY, f64::min);
+        best.is_finite().then_some(best)
+    }
+
+    fn search(adj: &[Vec<(usize, f64)>], src: usize) -> HashMap<usize, f64> {
+        let mut dist = HashMap::new();
+        let mut heap = BinaryHeap::new();
+        heap.push(Edge::new(NodeIndex::new(src), NodeIndex::new(src), -0.0));
+
+        while let Some(Edge { v, wt, .. }) = heap.pop() {
+            match dist.entry(v.index()) {
+                Entry::Vacant(slot) => slot.insert(-wt),
+                Entry::Occupied(_) => continue,
+            };
+            for &(x, w) in &adj[v.index()] {
+                if !dist.contains_key(&x) {
+                    heap.push(Edge::new(v, NodeIndex::new(x), wt - w));
+                }
+            }
+        }
+        dist
+    }
+}
+
+/// Out-neighbor bitsets built by `Grf::to_bit_adjacency`. Weights and
+/// parallel edges are not kept; unknown ids have no neighbors.
+pub struct BitAdjacency {
+    ids: Vec<usize>,
+    pos: HashMap<usize, usize>,
+    rows: Vec<Vec<u64>>,
+}
+
+impl BitAdjacency {
+    pub fn has_edge(&self, from: usize, to: usize) -> bool {
+        match (self.pos.get(&from), self.pos.get(&to)) {
+            (Some(&u), Some(&v)) => self.rows[u][v / 64] >> (v % 64) & 1 == 1,
+            _ => false,
+        }
+    }
+
+    /// Distinct out-neighbors of `id`, in insertion order of the nodes.
+    pub fn neighbors(&self, id: usize) -> Vec<usize> {
+        self.row(id).map_or_else(Vec::new, |row| self.members(row.iter().copied()))
+    }
+
+    /// Nodes that both `a` and `b` have an edge to.
+    pub fn common_neighbors(&self, a: usize, b: usize) -> Vec<usize> {
+        match (self.row(a), self.row(b)) {
+            (Some(x), Some(y)) => self.members(x.iter().zip(y).map(|(p, q)| p & q)),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Number of `common_neighbors`, without listing them.
+    pub fn common_count(&self, a: usize, b: usize) -> usize {
+        match (self.row(a), self.row(b)) {
+            (Some(x), Some(y)) => x.iter().zip(y).map(|(p, q)| (p & q).count_ones() as usize).sum(),
+            _ => 0,
+        }
+    }
+
+    fn row(&self, id: usize) -> Option<&[u64]> {
+        self.pos.get(&id).map(|&u| self.rows[u].as_slice())
+    }
+
+    fn members(&self, words: impl Iterator<Item = u64>) -> Vec<usize> {
+        let mut res = Vec::new();
+        for (i, mut w) in words.enumerate() {
+            while w != 0 {
+                res.push(self.ids[i * 64 + w.trailing_zeros() as usize]);
+                w &= w - 1;
+            }
+        }
+        res
+    }
+}
+
+/// Disjoint sets over `0..n` with path compression and union by rank.
+pub struct UnionFind {
+    parent: Vec<usize>,
+    rank: Vec<u8>,
+}
+
+impl UnionFind {
+    pub fn new(n: usize) -> Self {
+        Self {
+            parent: (0..n).collect(),
+            rank: vec![0; n],
+        }
+    }
+
+    pub fn find(&mut self, x: usize) -> usize {
+        let mut root = x;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        let mut curr = x;
+        while self.parent[curr] != root {
+            let next = self.parent[curr];
+            self.parent[curr] = root;
+            curr = next;
+        }
+        root
+    }
+
+    /// Returns false if `a` and `b` were already in the same set.
+    pub fn union(&mut self, a: usize, b: usize) -> bool {
+        let (ra, rb) = (self.find(a), self.find(b));
+        if ra == rb {
+            return false;
+        }
+        match self.rank[ra].cmp(&self.rank[rb]) {
+            Ordering::Less => self.parent[ra] = rb,
+            Ordering::Greater => self.parent[rb] = ra,
+            Ordering::Equal => {
+                self.parent[rb] = ra;
+                self.rank[ra] += 1;
+            }
+        }
+        true
+    }
+
+    pub fn connected(&mut self, a: usize, b: usize) -> bool {
+        self.find(a) == self.find(b)
+    }
+}
+
 #[derive(Debug)]
 struct Edge {
     u: NodeIndex,
@@ -1,60 +1,377 @@
 pub mod parser;
 
-use std::sync::Arc;
-use parking_lot::RwLock;
+use std::{collections::VecDeque, panic::{self, AssertUnwindSafe}, sync::{atomic::{AtomicU64, Ordering}, Arc, Weak}, thread, time::{Duration, Instant}};
+use parking_lot::{Mutex, RwLock};
 use crossbeam::channel;
-use dashmap::DashMap;
+use dashmap::{mapref::entry::Entry, DashMap};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CoreError {
+    #[error("no event consumer registered")]
+    NoConsumer,
+    #[error("event channel closed")]
+    ChannelClosed,
+    #[error("event channel full")]
+    ChannelFull,
+    #[error("core is not running")]
+    NotRunning,
+    #[error("core is already running")]
+    AlreadyRunning,
+    #[error("timed out")]
+    Timeout,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// A view of the `Core` cache whose keys are prefixed with a namespace.
+pub struct ScopedCache<'a> {
+    core: &'a Core,
+    prefix: String,
+}
+
+type Handler = Box<dyn Fn(&Event) + Send + Sync>;
 
 pub struct Core {
     state: Arc<RwLock<State>>,
     cache: Arc<DashMap<String, Vec<u8>>>,
-    tx: channel::Sender<Event>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    handlers: Arc<RwLock<Vec<Handler>>>,
+    log: Arc<Mutex<EventLog>>,
+    transition: Mutex<()>,
+    watchdog: Arc<Watchdog>,
+    tx: Arc<channel::Sender<Event>>,
     rx: channel::Receiver<Event>,
 }
 
+struct EventLog {
+    events: VecDeque<Event>,
+    cap: usize,
+}
+
+/// Shared with the dispatcher thread, which marks the event being handled,
+/// and the watchdog thread, which reports it once it runs too long.
+struct Watchdog {
+    timeout: Mutex<Option<Duration>>,
+    busy: Mutex<Option<(u64, Instant)>>,
+    timeouts: AtomicU64,
+}
+
 struct State {
     running: bool,
     workers: usize,
+    max_value_bytes: Option<usize>,
 }
 
-#[derive(Debug)]
-enum Event {
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Event {
     Start,
     Stop,
     Error(String),
+    /// Sent after `Start` or `Stop` on every change of the running state;
+    /// the older variants are kept so existing handlers still match.
+    StateChanged { from: bool, to: bool },
 }
 
 impl Core {
     pub fn new(workers: usize) -> Self {
-        let (tx, rx) = channel::bounded(1024);
+        Self::with_capacity(workers, 1024)
+    }
+
+    /// `channel_cap` bounds the events waiting for handlers. A small bound
+    /// applies backpressure sooner (sends fail with `ChannelFull`, except
+    /// `submit_timeout`, which waits); a large one absorbs bursts at the cost
+    /// of memory held by queued events.
+    pub fn with_capacity(workers: usize, channel_cap: usize) -> Self {
+        let (tx, rx) = channel::bounded(channel_cap);
         Self {
             state: Arc::new(RwLock::new(State {
                 running: false,
                 workers,
+                max_value_bytes: None,
             })),
             cache: Arc::new(DashMap::new()),
-            tx,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            handlers: Arc::new(RwLock::new(Vec::new())),
+            log: Arc::new(Mutex::new(EventLog {
+                events: VecDeque::new(),
+                cap: 256,
+            })),
+            transition: Mutex::new(()),
+            watchdog: Arc::new(Watchdog {
+                timeout: Mutex::new(None),
+                busy: Mutex::new(None),
+                timeouts: AtomicU64::new(0),
+            }),
+            tx: Arc::new(tx),
             rx,
         }
     }
 
-    pub fn start(&self) {
-        let mut state = self.state.write();
-        if !state.running {
-            state.running = true;
-            self.tx.send(Event::Start).unwrap();
+    /// Registers a handler that receives every event. The first call spawns
+    /// the thread that drains the channel, which exits once `Core` is dropped.
+    /// A handler that panics only loses that event; dispatch carries on.
+    pub fn on_event<F>(&self, f: F)
+    where
+        F: Fn(&Event) + Send + Sync + 'static,
+    {
+        let mut handlers = self.handlers.write();
+        handlers.push(Box::new(f));
+        if handlers.len() == 1 {
+            let rx = self.rx.clone();
+            let handlers = Arc::clone(&self.handlers);
+            let watchdog = Arc::clone(&self.watchdog);
+            thread::spawn(move || {
+                for (seq, ev) in rx.iter().enumerate() {
+                    *watchdog.busy.lock() = Some((seq as u64, Instant::now()));
+                    for h in handlers.read().iter() {
+                        let _ = panic::catch_unwind(AssertUnwindSafe(|| h(&ev)));
+                    }
+                    *watchdog.busy.lock() = None;
+                }
+            });
+        }
+    }
+
+    /// Warns when handling a single event takes longer than `timeout`: an
+    /// `Event::Error("handler timeout")` is queued and `handler_timeouts`
+    /// counts it. The stalled handler keeps running, so subscribers see the
+    /// warning once the dispatcher is free again; `recent_events` shows it
+    /// right away.
+    pub fn set_handler_timeout(&self, timeout: Duration) {
+        if self.watchdog.timeout.lock().replace(timeout).is_some() {
+            return;
         }
+        let watchdog = Arc::clone(&self.watchdog);
+        let log = Arc::clone(&self.log);
+        let tx = Arc::downgrade(&self.tx);
+        thread::spawn(move || watch(&watchdog, &log, &tx));
+    }
+
+    /// Events whose handling has outlasted the handler timeout.
+    pub fn handler_timeouts(&self) -> u64 {
+        self.watchdog.timeouts.load(Ordering::Relaxed)
     }
 
-    pub fn stop(&self) {
-        let mut state = self.state.write();
-        if state.running {
-            state.running = false;
-            self.tx.send(Event::Stop).unwrap();
+    /// Fails with `CoreError::NoConsumer` until `on_event` has been called,
+    /// since nothing would drain the bounded event channel. Like `stop`, the
+    /// core is started even if the `Start` events cannot be queued.
+    pub fn start(&self) -> Result<(), CoreError> {
+        if self.handlers.read().is_empty() {
+            return Err(CoreError::NoConsumer);
+        }
+
+        // Held while sending, so concurrent `start`/`stop` calls queue their
+        // events in the order the state changed. The state lock is released
+        // first, since handlers may need it.
+        let _transition = self.transition.lock();
+        if std::mem::replace(&mut self.state.write().running, true) {
+            return Err(CoreError::AlreadyRunning);
+        }
+        for ev in [Event::Start, Event::StateChanged { from: false, to: true }] {
+            self.try_send(ev)?;
+        }
+        Ok(())
+    }
+
+    /// The core is stopped even if the `Stop` events cannot be queued, in
+    /// which case the error says why.
+    pub fn stop(&self) -> Result<(), CoreError> {
+        let _transition = self.transition.lock();
+        if !std::mem::replace(&mut self.state.write().running, false) {
+            return Ok(());
+        }
+        for ev in [Event::Stop, Event::StateChanged { from: true, to: false }] {
+            self.try_send(ev)?;
+        }
+        Ok(())
+    }
+
+    pub fn submit(&self, ev: Event) -> Result<(), CoreError> {
+        if !self.is_running() {
+            return Err(CoreError::NotRunning);
+        }
+        self.try_send(ev)
+    }
+
+    pub fn submit_timeout(&self, ev: Event, timeout: Duration) -> Result<(), CoreError> {
+        if !self.is_running() {
+            return Err(CoreError::NotRunning);
+        }
+        self.dispatch(ev, |ev| {
+            self.tx.send_timeout(ev, timeout).map_err(|e| match e {
+                channel::SendTimeoutError::Timeout(_) => CoreError::Timeout,
+                channel::SendTimeoutError::Disconnected(_) => CoreError::ChannelClosed,
+            })
+        })
+    }
+
+    /// Unlike `submit`, works whether or not the core is running, but still
+    /// needs a handler registered with `on_event` to drain the channel.
+    pub fn report_error(&self, msg: impl Into<String>) -> Result<(), CoreError> {
+        if self.handlers.read().is_empty() {
+            return Err(CoreError::NoConsumer);
+        }
+        self.try_send(Event::Error(msg.into()))
+    }
+
+    fn try_send(&self, ev: Event) -> Result<(), CoreError> {
+        self.dispatch(ev, |ev| {
+            self.tx.try_send(ev).map_err(|e| match e {
+                channel::TrySendError::Full(_) => CoreError::ChannelFull,
+                channel::TrySendError::Disconnected(_) => CoreError::ChannelClosed,
+            })
+        })
+    }
+
+    /// Sends `ev`, then logs it. The log lock is only taken once the event is
+    /// queued, so a handler may run before its own event shows up in the log.
+    fn dispatch<F>(&self, ev: Event, send: F) -> Result<(), CoreError>
+    where
+        F: FnOnce(Event) -> Result<(), CoreError>,
+    {
+        send(ev.clone())?;
+        self.log.lock().push(ev);
+        Ok(())
+    }
+
+    /// The most recently enqueued events, oldest first.
+    pub fn recent_events(&self) -> Vec<Event> {
+        self.log.lock().events.iter().cloned().collect()
+    }
+
+    pub fn set_event_log_capacity(&self, cap: usize) {
+        let mut log = self.log.lock();
+        log.cap = cap;
+        while log.events.len() > cap {
+            log.events.pop_front();
         }
     }
 
     pub fn is_running(&self) -> bool {
         self.state.read().running
     }
-}
\ No newline at end of file
+
+    pub fn workers(&self) -> usize {
+        self.state.read().workers
+    }
+
+    pub fn set_max_value_bytes(&self, limit: usize) {
+        self.state.write().max_value_bytes = Some(limit);
+    }
+
+    pub fn cache_put(&self, key: &str, val: Vec<u8>) -> bool {
+        if let Some(limit) = self.state.read().max_value_bytes {
+            if val.len() > limit {
+                return false;
+            }
+        }
+        self.cache.insert(key.to_string(), val);
+        true
+    }
+
+    pub fn cache_get(&self, key: &str) -> Option<Vec<u8>> {
+        let val = self.cache.get(key).map(|v| v.clone());
+        let counter = if val.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        val
+    }
+
+    /// Returns the cached value, or computes and stores it. `f` runs with the
+    /// key's shard locked, so concurrent callers for the same key wait for the
+    /// first instead of computing again; it must not use the cache itself.
+    /// A value over the size limit is returned without being stored.
+    pub fn cache_get_or_insert_with<F: FnOnce() -> Vec<u8>>(&self, key: &str, f: F) -> Vec<u8> {
+        match self.cache.entry(key.to_string()) {
+            Entry::Occupied(e) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                e.get().clone()
+            }
+            Entry::Vacant(e) => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                let val = f();
+                if let Some(limit) = self.state.read().max_value_bytes {
+                    if val.len() > limit {
+                        return val;
+                    }
+                }
+                e.insert(val.clone());
+                val
+            }
+        }
+    }
+
+    pub fn cache_scoped(&self, namespace: &str) -> ScopedCache<'_> {
+        ScopedCache {
+            core: self,
+            prefix: format!("{}:{}:", namespace.len(), namespace),
+        }
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl EventLog {
+    fn push(&mut self, ev: Event) {
+        if self.cap == 0 {
+            return;
+        }
+        if self.events.len() == self.cap {
+            self.events.pop_front();
+        }
+        self.events.push_back(ev);
+    }
+}
+
+/// The watchdog thread: polls the event being handled and reports each one
+/// that outlasts the timeout once. Exits when `Core` is dropped.
+fn watch(watchdog: &Watchdog, log: &Mutex<EventLog>, tx: &Weak<channel::Sender<Event>>) {
+    let mut reported = None;
+    loop {
+        let timeout = watchdog.timeout.lock().unwrap_or(Duration::MAX);
+        thread::sleep((timeout / 4).clamp(Duration::from_millis(1), Duration::from_millis(50)));
+        let Some(tx) = tx.upgrade() else {
+            return;
+        };
+
+        let Some((seq, since)) = *watchdog.busy.lock() else {
+            continue;
+        };
+        if since.elapsed() < timeout || reported == Some(seq) {
+            continue;
+        }
+        reported = Some(seq);
+        watchdog.timeouts.fetch_add(1, Ordering::Relaxed);
+        let ev = Event::Error("handler timeout".into());
+        if tx.try_send(ev.clone()).is_ok() {
+            log.lock().push(ev);
+        }
+    }
+}
+
+impl ScopedCache<'_> {
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.core.cache_get(&format!("{}{}", self.prefix, key))
+    }
+
+    pub fn put(&self, key: &str, val: Vec<u8>) -> bool {
+        self.core.cache_put(&format!("{}{}", self.prefix, key), val)
+    }
+
+    /// Removes this namespace's entries only.
+    pub fn clear(&self) {
+        self.core.cache.retain(|k, _| !k.starts_with(&self.prefix));
+    }
+}
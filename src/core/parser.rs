@@ -1,85 +1,315 @@
-use std::{collections::{HashMap, VecDeque}, sync::Arc};
+use std::{borrow::Cow, collections::{HashMap, VecDeque}, sync::Arc, time::Instant};
 use parking_lot::RwLock;
+use rayon::prelude::*;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum ParseError {
-    #[error("invalid token sequence: {0}")]
-    InvalidToken(String),
+    #[error("invalid token sequence: {msg}")]
+    InvalidToken { msg: String, tok: Tok },
     #[error("unexpected end of input")]
     UnexpectedEOF,
     #[error("syntax error: {0}")]
-    Syntax(String),
+    Syntax(String, usize),
+}
+
+impl ParseError {
+    /// Character offset into the input, `None` for `UnexpectedEOF`.
+    pub fn pos(&self) -> Option<usize> {
+        match self {
+            Self::InvalidToken { tok, .. } => Some(tok.pos),
+            Self::Syntax(_, pos) => Some(*pos),
+            Self::UnexpectedEOF => None,
+        }
+    }
+
+    /// The offending line of `input` with a `^` under the error column on
+    /// the line below. Tabs before the column are kept so the caret lines up.
+    pub fn render(&self, input: &str) -> String {
+        let chars: Vec<char> = input.chars().collect();
+        let pos = self.pos().unwrap_or(chars.len()).min(chars.len());
+        let start = chars[..pos].iter().rposition(|&c| c == '\n').map_or(0, |i| i + 1);
+        let end = chars[pos..].iter().position(|&c| c == '\n').map_or(chars.len(), |i| pos + i);
+
+        let line: String = chars[start..end].iter().collect();
+        let pad: String = chars[start..pos]
+            .iter()
+            .map(|&c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        format!("{}\n{}^", line.trim_end_matches('\r'), pad)
+    }
 }
 
 type Result<T> = std::result::Result<T, ParseError>;
+type Unit = (f64, [i8; 3]);
+pub type Resolver = Arc<dyn Fn(&str) -> Option<f64> + Send + Sync>;
+pub type Observer = Arc<dyn Fn(&str, f64) + Send + Sync>;
+
+/// Deepest nesting of factors a parser accepts.
+const MAX_DEPTH: usize = 200;
 
 #[derive(Debug, Clone)]
 pub struct Tok {
-    val: String,
+    val: Cow<'static, str>,
     pos: usize,
     typ: TokType,
 }
 
-#[derive(Debug, Clone, PartialEq)]
-enum TokType {
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TokType {
     Id,
     Op,
     Num,
     Sym,
 }
 
+impl Tok {
+    pub fn val(&self) -> &str {
+        &self.val
+    }
+
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn typ(&self) -> &TokType {
+        &self.typ
+    }
+}
+
+/// A magnitude in base SI units with the exponents of metres, kilograms and
+/// seconds in `dim`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quantity {
+    pub val: f64,
+    pub dim: [i8; 3],
+}
+
+/// A result of any of the parsers: `Num`, `Bool` and `Tuple` come from
+/// `parse`, the others from `parse_int`, `parse_quantity` and
+/// `parse_duration` (in seconds).
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Value {
+    Num(f64),
+    Int(i64),
+    Bool(bool),
+    Tuple(Vec<f64>),
+    Quantity(Quantity),
+    Duration(f64),
+}
+
+impl Value {
+    /// The value of a `Num`, or of an `Int` converted.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Self::Num(v) => Some(v),
+            Self::Int(v) => Some(v as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Self::Int(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Self::Bool(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_tuple(&self) -> Option<&[f64]> {
+        match self {
+            Self::Tuple(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_quantity(&self) -> Option<Quantity> {
+        match *self {
+            Self::Quantity(q) => Some(q),
+            _ => None,
+        }
+    }
+
+    /// Seconds of a `Duration`.
+    pub fn as_duration(&self) -> Option<f64> {
+        match *self {
+            Self::Duration(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+/// How `parse_int` handles results outside the `i64` range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntMode {
+    #[default]
+    Checked,
+    Wrapping,
+    Saturating,
+}
+
+/// Characters in `ident_chars` are accepted anywhere in an identifier, in
+/// addition to the default `[A-Za-z0-9_]`.
+#[derive(Debug, Clone, Default)]
+pub struct LexOptions {
+    pub ident_chars: Vec<char>,
+}
+
 pub struct Prs {
     toks: VecDeque<Tok>,
+    opts: LexOptions,
     ctx: Arc<RwLock<PrsCtx>>,
+    scopes: Vec<HashMap<String, f64>>,
+    deadline: Option<Instant>,
+    trace: Option<Vec<String>>,
+    units: fn(&str) -> Option<Unit>,
+    int_mode: IntMode,
+    zero_eps: f64,
+    undefined_as_zero: bool,
+    offset: usize,
     idx: usize,
+    depth: usize,
 }
 
-struct PrsCtx {
+#[derive(Default)]
+pub struct PrsCtx {
     syms: HashMap<String, f64>,
-    depth: usize,
+    resolver: Option<Resolver>,
+    observer: Option<Observer>,
+}
+
+impl PrsCtx {
+    /// A context holding only `ans`, seeded to 0.
+    pub fn new() -> Self {
+        let mut ctx = Self::default();
+        ctx.set("ans", 0.0);
+        ctx
+    }
+
+    /// `new` with `vars` added, which may replace `ans`.
+    fn with_vars(vars: &HashMap<String, f64>) -> Self {
+        let mut ctx = Self::new();
+        ctx.syms.extend(vars.iter().map(|(k, v)| (k.clone(), *v)));
+        ctx
+    }
+
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.syms.get(name).copied()
+    }
+
+    pub fn set(&mut self, name: &str, val: f64) {
+        self.syms.insert(name.to_string(), val);
+    }
 }
 
 impl Prs {
     pub fn new(input: &str) -> Self {
+        Self::with_options(input, &LexOptions::default())
+    }
+
+    pub fn with_options(input: &str, opts: &LexOptions) -> Self {
         Self {
-            toks: Self::lex(input),
-            ctx: Arc::new(RwLock::new(PrsCtx {
-                syms: HashMap::new(),
-                depth: 0,
-            })),
+            toks: Self::lex(input, opts),
+            opts: opts.clone(),
+            ctx: Arc::new(RwLock::new(PrsCtx::new())),
+            scopes: Vec::new(),
+            deadline: None,
+            trace: None,
+            units: Self::unit,
+            int_mode: IntMode::default(),
+            zero_eps: 0.0,
+            undefined_as_zero: false,
+            offset: 0,
             idx: 0,
+            depth: 0,
+        }
+    }
+
+    /// Reads and writes variables in `ctx`, which other parsers may share.
+    /// `ans` is only defined if `ctx` came from `PrsCtx::new`.
+    pub fn with_context(input: &str, ctx: Arc<RwLock<PrsCtx>>) -> Self {
+        Self {
+            ctx,
+            ..Self::with_options(input, &LexOptions::default())
+        }
+    }
+
+    /// Shifts every token and error position by `base_offset`, for input
+    /// taken from a larger document.
+    pub fn with_offset(input: &str, base_offset: usize) -> Self {
+        let mut prs = Self::new(input);
+        prs.offset = base_offset;
+        prs.shift_positions();
+        prs
+    }
+
+    /// Replaces the input, keeping the context and its variables.
+    pub fn reset(&mut self, input: &str) {
+        self.toks = Self::lex(input, &self.opts);
+        self.shift_positions();
+        self.scopes.clear();
+        self.idx = 0;
+    }
+
+    fn shift_positions(&mut self) {
+        for tok in &mut self.toks {
+            tok.pos += self.offset;
         }
     }
 
-    fn lex(input: &str) -> VecDeque<Tok> {
+    fn lex(input: &str, opts: &LexOptions) -> VecDeque<Tok> {
+        let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_' || opts.ident_chars.contains(&c);
+
         let mut toks = VecDeque::new();
         let mut pos = 0;
         let mut chars = input.chars().peekable();
 
         while let Some(&c) = chars.peek() {
             match c {
-                '0'..='9' => {
+                // A run of digits, points and separators is one token, so `1.2.3`
+                // and `..5` fail as numbers rather than splitting or dropping points.
+                // A point followed by anything else, as in `order.total`, is skipped.
+                c if c.is_ascii_digit()
+                    || (c == '.'
+                        && !is_ident(c)
+                        && chars.clone().nth(1).is_some_and(|d| d.is_ascii_digit() || d == '.')) =>
+                {
+                    let start = pos;
                     let mut num = String::new();
                     while let Some(&d) = chars.peek() {
-                        if d.is_ascii_digit() || d == '.' {
-                            num.push(d);
-                            chars.next();
-                            pos += 1;
-                        } else {
+                        let hex = num.starts_with("0x") || num.starts_with("0X");
+                        let ok = d.is_ascii_digit()
+                            || d == '.'
+                            || d == '_'
+                            || (num == "0" && (d == 'x' || d == 'X'))
+                            || (hex && (d.is_ascii_hexdigit() || d == 'p' || d == 'P'))
+                            || (hex && (d == '+' || d == '-') && num.ends_with(['p', 'P']));
+                        if !ok {
                             break;
                         }
+                        num.push(d);
+                        chars.next();
+                        pos += 1;
                     }
                     toks.push_back(Tok {
-                        val: num,
-                        pos,
+                        val: Cow::Owned(Self::normalize_num(&num).unwrap_or(num)),
+                        pos: start,
                         typ: TokType::Num,
                     });
                 }
-                'a'..='z' | 'A'..='Z' | '_' => {
+                c if is_ident(c) => {
+                    let start = pos;
                     let mut id = String::new();
                     while let Some(&c) = chars.peek() {
-                        if c.is_ascii_alphanumeric() || c == '_' {
+                        if is_ident(c) {
                             id.push(c);
                             chars.next();
                             pos += 1;
@@ -88,17 +318,49 @@ impl Prs {
                         }
                     }
                     toks.push_back(Tok {
-                        val: id,
-                        pos,
+                        val: Cow::Owned(id),
+                        pos: start,
                         typ: TokType::Id,
                     });
                 }
-                '+' | '-' | '*' | '/' | '^' => {
+                '+' | '-' | '*' | '/' | '^' | '<' | '>' => {
+                    chars.next();
+                    let val = match (c, chars.peek()) {
+                        ('/', Some('/')) => "//",
+                        ('<', Some('=')) => "<=",
+                        ('>', Some('=')) => ">=",
+                        _ => Self::tok_str(c),
+                    };
+                    if val.len() == 2 {
+                        chars.next();
+                    }
                     toks.push_back(Tok {
-                        val: c.to_string(),
+                        val: Cow::Borrowed(val),
                         pos,
                         typ: TokType::Op,
                     });
+                    pos += val.len();
+                }
+                '=' | '!' if matches!((c, chars.clone().nth(1)), ('=', Some('=' | '>')) | ('!', Some('='))) => {
+                    chars.next();
+                    let val = match chars.next() {
+                        Some('>') => "=>",
+                        _ if c == '=' => "==",
+                        _ => "!=",
+                    };
+                    toks.push_back(Tok {
+                        val: Cow::Borrowed(val),
+                        pos,
+                        typ: if val == "=>" { TokType::Sym } else { TokType::Op },
+                    });
+                    pos += 2;
+                }
+                '|' | '{' | '}' | '(' | ')' | ';' | '=' | ',' => {
+                    toks.push_back(Tok {
+                        val: Cow::Borrowed(Self::tok_str(c)),
+                        pos,
+                        typ: TokType::Sym,
+                    });
                     chars.next();
                     pos += 1;
                 }
@@ -111,22 +373,233 @@ impl Prs {
         toks
     }
 
-    pub fn parse(&mut self) -> Result<f64> {
-        self.expr()
+    /// Strips `_` separators, which must sit between two digits, and writes
+    /// hex literals (`0xff`, `0x1.8p3`) in decimal. `None` if malformed
+    /// (including a binary exponent beyond ±1100) or too large for an `f64`,
+    /// in which case the raw text is kept and fails to parse as a number.
+    fn normalize_num(raw: &str) -> Option<String> {
+        let b = raw.as_bytes();
+        for (i, &c) in b.iter().enumerate() {
+            let between = i > 0
+                && b[i - 1].is_ascii_hexdigit()
+                && b.get(i + 1).is_some_and(u8::is_ascii_hexdigit);
+            if c == b'_' && !between {
+                return None;
+            }
+        }
+
+        let s = raw.replace('_', "");
+        let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) else {
+            return Some(s);
+        };
+        let (mant, exp) = match hex.split_once(['p', 'P']) {
+            Some((m, e)) => (m, e.parse::<i32>().ok().filter(|e| (-1100..=1100).contains(e))?),
+            None if !hex.contains('.') => {
+                return u128::from_str_radix(hex, 16).ok().map(|v| v.to_string());
+            }
+            None => (hex, 0),
+        };
+        let (int, frac) = mant.split_once('.').unwrap_or((mant, ""));
+        if int.is_empty() && frac.is_empty() {
+            return None;
+        }
+
+        let mut val = 0.0;
+        for c in int.chars().chain(frac.chars()) {
+            val = val * 16.0 + c.to_digit(16)? as f64;
+        }
+        // Scaled in two halves so results near the subnormal range survive.
+        let exp = (exp as i64 - 4 * frac.len() as i64).clamp(-4000, 4000) as i32;
+        let val = val * 2f64.powi(exp / 2) * 2f64.powi(exp - exp / 2);
+        val.is_finite().then(|| val.to_string())
+    }
+
+    fn tok_str(c: char) -> &'static str {
+        match c {
+            '+' => "+",
+            '-' => "-",
+            '*' => "*",
+            '/' => "/",
+            '^' => "^",
+            '<' => "<",
+            '>' => ">",
+            '|' => "|",
+            '{' => "{",
+            '}' => "}",
+            ';' => ";",
+            '=' => "=",
+            ',' => ",",
+            '(' => "(",
+            ')' => ")",
+            _ => unreachable!("not an operator or symbol: {}", c),
+        }
+    }
+
+    pub fn set_var(&self, name: &str, val: f64) {
+        self.ctx.write().set(name, val);
+    }
+
+    /// Consulted for identifiers that are neither in scope nor set in the
+    /// context. Shared by every parser using the same context.
+    pub fn set_resolver(&self, f: Resolver) {
+        self.ctx.write().resolver = Some(f);
+    }
+
+    /// Evaluates unknown identifiers to 0 instead of failing, after scopes,
+    /// the context and any resolver have been consulted.
+    pub fn set_undefined_as_zero(&mut self, on: bool) {
+        self.undefined_as_zero = on;
+    }
+
+    /// Called with each binary operation (`"1 + 2"`) and its result as it is
+    /// evaluated. Shared by every parser using the same context.
+    pub fn set_observer(&self, f: Observer) {
+        self.ctx.write().observer = Some(f);
+    }
+
+    /// Treats divisors within `eps` of zero as division by zero in the float
+    /// and quantity parsers. The default of 0 only rejects exact zeros.
+    pub fn set_zero_tolerance(&mut self, eps: f64) {
+        self.zero_eps = eps;
+    }
+
+    /// Evaluates the input. A trailing comparison (`x < 3`) gives a `Bool`
+    /// and a comma-separated list a `Tuple`; otherwise the result is a `Num`,
+    /// which is also stored in `ans` for later inputs. Tokens left over after
+    /// the result are an error.
+    pub fn parse(&mut self) -> Result<Value> {
+        let val = self.stmts()?;
+        let res = if let Some(b) = self.compare(val)? {
+            Value::Bool(b)
+        } else if self.peek().is_some_and(|t| t.val == ",") {
+            let mut vals = vec![val];
+            while self.peek().is_some_and(|t| t.val == ",") {
+                self.next();
+                vals.push(self.expr()?);
+            }
+            Value::Tuple(vals)
+        } else {
+            Value::Num(val)
+        };
+        self.finish()?;
+
+        if let Value::Num(val) = res {
+            self.ctx.write().set("ans", val);
+        }
+        Ok(res)
+    }
+
+    /// `parse` for callers that only handle numbers; a comparison or list is
+    /// an error.
+    pub fn parse_f64(&mut self) -> Result<f64> {
+        let val = self.parse()?;
+        val.as_f64()
+            .ok_or_else(|| ParseError::Syntax(format!("expected a number, found {:?}", val), self.here()))
+    }
+
+    pub fn parse_with_deadline(&mut self, deadline: Instant) -> Result<f64> {
+        self.deadline = Some(deadline);
+        let res = self.parse_f64();
+        self.deadline = None;
+        res
+    }
+
+    /// Like `parse`, also returning each binary reduction in evaluation
+    /// order, e.g. `["3 * 4 = 12", "2 + 12 = 14"]` for `2 + 3 * 4`.
+    pub fn parse_trace(&mut self) -> Result<(f64, Vec<String>)> {
+        self.trace = Some(Vec::new());
+        let res = self.parse_f64();
+        let trace = self.trace.take().unwrap_or_default();
+        Ok((res?, trace))
+    }
+
+    /// Evaluates a comma-separated list of expressions, e.g. `1, 2+3, 4` to
+    /// `[1, 5, 4]`. A single expression gives a one-element list.
+    pub fn parse_tuple(&mut self) -> Result<Vec<f64>> {
+        let mut vals = vec![self.expr()?];
+        while self.peek().is_some_and(|t| t.val == ",") {
+            self.next();
+            vals.push(self.expr()?);
+        }
+        Ok(vals)
+    }
+
+    /// Evaluates without writing to a context other parsers may share:
+    /// top-level assignments bind in a scope private to this evaluation, and
+    /// `ans` is read but not updated.
+    fn eval_local(&mut self) -> Result<f64> {
+        self.scopes.push(HashMap::new());
+        let val = self.stmts().and_then(|val| {
+            self.finish()?;
+            Ok(val)
+        });
+        self.scopes.clear();
+        val
+    }
+
+    fn stmts(&mut self) -> Result<f64> {
+        let mut val = self.stmt()?;
+
+        while self.peek().is_some_and(|t| t.val == ";") {
+            self.next();
+            val = self.stmt()?;
+        }
+        Ok(val)
+    }
+
+    /// `name = expr` binds in the innermost `{}` scope, or globally when not
+    /// inside a block, and evaluates to the assigned value.
+    fn stmt(&mut self) -> Result<f64> {
+        let is_assign = matches!(
+            (self.toks.get(self.idx), self.toks.get(self.idx + 1)),
+            (Some(a), Some(b)) if a.typ == TokType::Id && b.val == "="
+        );
+        if !is_assign {
+            return self.expr();
+        }
+
+        let name = self.next().unwrap().val.into_owned();
+        self.next();
+        let val = self.expr()?;
+        match self.scopes.last_mut() {
+            Some(scope) => {
+                scope.insert(name, val);
+            }
+            None => {
+                self.ctx.write().syms.insert(name, val);
+            }
+        }
+        Ok(val)
+    }
+
+    fn lookup(&self, name: &str) -> Result<f64> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+            .or_else(|| self.ctx.read().syms.get(name).copied())
+            .or_else(|| {
+                let resolver = self.ctx.read().resolver.clone();
+                resolver.and_then(|f| f(name))
+            })
+            .or(self.undefined_as_zero.then_some(0.0))
+            .ok_or_else(|| ParseError::Syntax(format!("undefined variable: {}", name), self.here()))
     }
 
     fn expr(&mut self) -> Result<f64> {
         let mut lhs = self.term()?;
 
         while let Some(tok) = self.peek() {
-            match tok.val.as_str() {
+            match tok.val.as_ref() {
                 "+" => {
                     self.next();
-                    lhs += self.term()?;
+                    let rhs = self.term()?;
+                    lhs = self.record(lhs, "+", rhs, lhs + rhs);
                 }
                 "-" => {
                     self.next();
-                    lhs -= self.term()?;
+                    let rhs = self.term()?;
+                    lhs = self.record(lhs, "-", rhs, lhs - rhs);
                 }
                 _ => break,
             }
@@ -135,21 +608,525 @@ impl Prs {
     }
 
     fn term(&mut self) -> Result<f64> {
-        let mut lhs = self.factor()?;
+        let mut lhs = self.nested(Self::factor)?;
+
+        while let Some(op) = self.peek().and_then(|t| Self::mul_op(&t.val)) {
+            self.next();
+            let rhs = self.nested(Self::factor)?;
+            if op != "*" && approx_eq(rhs, 0.0, self.zero_eps) {
+                return Err(ParseError::Syntax("division by zero".into(), self.here()));
+            }
+            let res = match op {
+                "*" => lhs * rhs,
+                "/" => lhs / rhs,
+                "//" => (lhs / rhs).floor(),
+                _ => lhs - rhs * (lhs / rhs).floor(),
+            };
+            lhs = self.record(lhs, op, rhs, res);
+        }
+        Ok(lhs)
+    }
+
+    /// `//` divides rounding down and `mod` takes the matching remainder,
+    /// which has the sign of the divisor: `-7 // 2` is `-4`, `-7 mod 2` is `1`.
+    fn mul_op(val: &str) -> Option<&'static str> {
+        match val {
+            "*" => Some("*"),
+            "/" => Some("/"),
+            "//" => Some("//"),
+            "mod" => Some("mod"),
+            _ => None,
+        }
+    }
+
+    /// Runs `f` one level deeper into nested factors (unary minus, bars,
+    /// blocks), failing past `MAX_DEPTH` instead of overflowing the stack.
+    fn nested<T>(&mut self, f: fn(&mut Self) -> Result<T>) -> Result<T> {
+        if self.depth >= MAX_DEPTH {
+            return Err(ParseError::Syntax("nesting too deep".into(), self.here()));
+        }
+        self.depth += 1;
+        let res = f(self);
+        self.depth -= 1;
+        res
+    }
+
+    fn record(&mut self, lhs: f64, op: &str, rhs: f64, res: f64) -> f64 {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.push(format!("{} {} {} = {}", lhs, op, rhs, res));
+        }
+        let observer = self.ctx.read().observer.clone();
+        if let Some(f) = observer {
+            f(&format!("{} {} {}", lhs, op, rhs), res);
+        }
+        res
+    }
+
+    fn factor(&mut self) -> Result<f64> {
+        if self.deadline.is_some_and(|d| Instant::now() >= d) {
+            return Err(ParseError::Syntax("parse deadline exceeded".into(), self.here()));
+        }
+
+        let tok = self.next().ok_or(ParseError::UnexpectedEOF)?;
+        
+        match tok.typ {
+            TokType::Num => tok.val.parse::<f64>().map_err(|_| {
+                ParseError::InvalidToken {
+                    msg: format!("invalid number: {}", tok.val),
+                    tok: tok.clone(),
+                }
+            }),
+            TokType::Id if tok.val == "case" && self.peek().is_some_and(|t| t.val == "{") => self.case(),
+            TokType::Id if tok.val == "let" && self.peek().is_some_and(|t| t.typ == TokType::Id) => self.let_in(),
+            TokType::Id if tok.val == "if" && self.peek().is_some_and(|t| t.val == "(") => self.if_else(),
+            TokType::Id if self.peek().is_some_and(|t| t.val == "(") => {
+                let args = self.args(Self::expr)?;
+                let args = args
+                    .into_iter()
+                    .map(|v| {
+                        if v.fract() == 0.0 && v.abs() < i64::MAX as f64 {
+                            Ok(v as i128)
+                        } else {
+                            Err(ParseError::Syntax(format!("{} expects integers", tok.val), tok.pos))
+                        }
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                self.call(&tok, &args).map(|v| v as f64)
+            }
+            TokType::Id => self.lookup(&tok.val),
+            TokType::Op if tok.val == "-" => Ok(-self.nested(Self::factor)?),
+            TokType::Sym if tok.val == "(" => {
+                let val = self.expr()?;
+                self.expect(")")?;
+                Ok(val)
+            }
+            TokType::Sym if tok.val == "|" => {
+                let val = self.expr()?;
+                match self.next() {
+                    Some(t) if t.val == "|" => Ok(val.abs()),
+                    Some(t) => Err(ParseError::InvalidToken {
+                        msg: format!("expected '|', found {}", t.val),
+                        tok: t,
+                    }),
+                    None => Err(ParseError::UnexpectedEOF),
+                }
+            }
+            TokType::Sym if tok.val == "{" => {
+                self.scopes.push(HashMap::new());
+                let val = self.stmts();
+                self.scopes.pop();
+                let val = val?;
+                match self.next() {
+                    Some(t) if t.val == "}" => Ok(val),
+                    Some(t) => Err(ParseError::InvalidToken {
+                        msg: format!("expected '}}', found {}", t.val),
+                        tok: t,
+                    }),
+                    None => Err(ParseError::UnexpectedEOF),
+                }
+            }
+            _ => Err(ParseError::InvalidToken {
+                msg: format!("unexpected token: {}", tok.val),
+                tok,
+            }),
+        }
+    }
+
+    /// `let name = expr in body` binds `name` while evaluating `body` only,
+    /// shadowing any outer `name`. The body extends as far as an expression
+    /// can, so `let x = 2 in x + 1` is `3`.
+    fn let_in(&mut self) -> Result<f64> {
+        let name = self.next().unwrap().val.into_owned();
+        self.expect("=")?;
+        let bound = self.expr()?;
+        self.expect("in")?;
+
+        self.scopes.push(HashMap::from([(name, bound)]));
+        let val = self.expr();
+        self.scopes.pop();
+        val
+    }
+
+    /// Parses `(arg, ...)` after a function name.
+    fn args<T>(&mut self, arg: fn(&mut Self) -> Result<T>) -> Result<Vec<T>> {
+        self.expect("(")?;
+        let mut vals = vec![arg(self)?];
+        while self.peek().is_some_and(|t| t.val == ",") {
+            self.next();
+            vals.push(arg(self)?);
+        }
+        self.expect(")")?;
+        Ok(vals)
+    }
+
+    /// Built-in functions over integers: `gcd(a, b)` and `lcm(a, b)`, both
+    /// non-negative, with `gcd(0, 0)` and `lcm(0, b)` equal to 0.
+    fn call(&self, name: &Tok, args: &[i128]) -> Result<i128> {
+        if !matches!(name.val.as_ref(), "gcd" | "lcm") {
+            return Err(ParseError::Syntax(format!("unknown function: {}", name.val), name.pos));
+        }
+        let &[a, b] = args else {
+            return Err(ParseError::Syntax(format!("{} takes 2 arguments", name.val), name.pos));
+        };
+        let g = gcd(a.unsigned_abs(), b.unsigned_abs()) as i128;
+        Ok(match name.val.as_ref() {
+            "gcd" => g,
+            _ if g == 0 => 0,
+            _ => (a / g * b).abs(),
+        })
+    }
+
+    /// `if(cond, then, else)`, with `cond` as in `case`. Only the selected
+    /// branch is evaluated, so an error in the other (say `1 / 0`) is never
+    /// raised.
+    fn if_else(&mut self) -> Result<f64> {
+        self.expect("(")?;
+        let taken = self.cond()?;
+        self.expect(",")?;
+        let val = if taken {
+            let val = self.expr()?;
+            self.expect(",")?;
+            self.skip_arg()?;
+            val
+        } else {
+            self.skip_arg()?;
+            self.expect(",")?;
+            self.expr()?
+        };
+        self.expect(")")?;
+        Ok(val)
+    }
+
+    /// Consumes an unevaluated argument, stopping before the `,` or `)` that
+    /// ends it and stepping over nested parentheses and blocks.
+    fn skip_arg(&mut self) -> Result<()> {
+        let mut depth = 0;
+        let mut empty = true;
+        loop {
+            let tok = self.peek().ok_or(ParseError::UnexpectedEOF)?;
+            match tok.val.as_ref() {
+                "(" | "{" => depth += 1,
+                ")" | "}" if depth > 0 => depth -= 1,
+                "," | ")" | "}" if depth == 0 => break,
+                _ => {}
+            }
+            self.next();
+            empty = false;
+        }
+        if empty {
+            let tok = self.next().ok_or(ParseError::UnexpectedEOF)?;
+            return Err(ParseError::InvalidToken {
+                msg: format!("unexpected token: {}", tok.val),
+                tok,
+            });
+        }
+        Ok(())
+    }
+
+    fn expect(&mut self, want: &str) -> Result<()> {
+        match self.next() {
+            Some(t) if t.val == want => Ok(()),
+            Some(t) => Err(ParseError::InvalidToken {
+                msg: format!("expected '{}', found {}", want, t.val),
+                tok: t,
+            }),
+            None => Err(ParseError::UnexpectedEOF),
+        }
+    }
+
+    /// Fails on the first token left over after a complete input.
+    fn finish(&mut self) -> Result<()> {
+        match self.next() {
+            Some(tok) => Err(ParseError::InvalidToken {
+                msg: format!("unexpected token: {}", tok.val),
+                tok,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// `case { cond => expr; ...; _ => expr }` evaluates to the first branch
+    /// whose condition holds, where a condition compares two expressions
+    /// (`<`, `<=`, `>`, `>=`, `==`, `!=`) or is a single nonzero expression.
+    /// Branches after the match are skipped unevaluated; no match is an error.
+    fn case(&mut self) -> Result<f64> {
+        self.next();
+        loop {
+            if self.peek().is_some_and(|t| t.val == "}") {
+                return Err(ParseError::Syntax("no case matched".into(), self.here()));
+            }
+            let default = matches!(
+                (self.toks.get(self.idx), self.toks.get(self.idx + 1)),
+                (Some(a), Some(b)) if a.val == "_" && b.val == "=>"
+            );
+            let matched = if default {
+                self.next();
+                true
+            } else {
+                self.cond()?
+            };
+            self.expect("=>")?;
+
+            if matched {
+                let val = self.expr()?;
+                while self.skip_branch()? {}
+                return Ok(val);
+            }
+            if !self.skip_branch()? {
+                return Err(ParseError::Syntax("no case matched".into(), self.here()));
+            }
+        }
+    }
+
+    fn cond(&mut self) -> Result<bool> {
+        let lhs = self.expr()?;
+        Ok(self.compare(lhs)?.unwrap_or(lhs != 0.0))
+    }
+
+    /// Applies the comparison operator after `lhs`, if one follows.
+    fn compare(&mut self, lhs: f64) -> Result<Option<bool>> {
+        let Some(op) = self.peek().map(|t| t.val.clone()) else {
+            return Ok(None);
+        };
+        let cmp: fn(&f64, &f64) -> bool = match op.as_ref() {
+            "<" => f64::lt,
+            "<=" => f64::le,
+            ">" => f64::gt,
+            ">=" => f64::ge,
+            "==" => f64::eq,
+            "!=" => f64::ne,
+            _ => return Ok(None),
+        };
+        self.next();
+        let rhs = self.expr()?;
+        Ok(Some(cmp(&lhs, &rhs)))
+    }
+
+    /// Consumes tokens up to and including the `;` or `}` ending the current
+    /// branch, stepping over nested blocks. `true` if more branches follow.
+    fn skip_branch(&mut self) -> Result<bool> {
+        let mut depth = 0;
+        loop {
+            let tok = self.next().ok_or(ParseError::UnexpectedEOF)?;
+            match tok.val.as_ref() {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(false),
+                "}" => depth -= 1,
+                ";" if depth == 0 => {
+                    if self.peek().is_some_and(|t| t.val == "}") {
+                        self.next();
+                        return Ok(false);
+                    }
+                    return Ok(true);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Evaluates with `i64` arithmetic. Division truncates toward zero like
+    /// Rust's `/`, so `7 / 2` is `3`. Division by zero is an error; overflow
+    /// is handled according to the `IntMode`, an error by default.
+    pub fn parse_int(&mut self) -> Result<i64> {
+        self.expr_int()
+    }
+
+    pub fn set_int_mode(&mut self, mode: IntMode) {
+        self.int_mode = mode;
+    }
+
+    fn int_op(&self, lhs: i64, op: &str, rhs: i64) -> Result<i64> {
+        let (lhs, rhs) = (lhs as i128, rhs as i128);
+        let exact = match op {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" => lhs / rhs,
+            "//" | "mod" => {
+                let (q, r) = (lhs / rhs, lhs % rhs);
+                let floor = r != 0 && (r < 0) != (rhs < 0);
+                match (op, floor) {
+                    ("//", true) => q - 1,
+                    ("//", false) => q,
+                    (_, true) => r + rhs,
+                    (_, false) => r,
+                }
+            }
+            _ => unreachable!("not an integer operator: {}", op),
+        };
+        self.int_result(exact)
+    }
+
+    fn int_result(&self, exact: i128) -> Result<i64> {
+        match self.int_mode {
+            IntMode::Checked => i64::try_from(exact).map_err(|_| self.overflow()),
+            IntMode::Wrapping => Ok(exact as i64),
+            IntMode::Saturating => Ok(exact.clamp(i64::MIN as i128, i64::MAX as i128) as i64),
+        }
+    }
+
+    fn expr_int(&mut self) -> Result<i64> {
+        let mut lhs = self.term_int()?;
+
+        while let Some(tok) = self.peek() {
+            match tok.val.as_ref() {
+                "+" => {
+                    self.next();
+                    let rhs = self.term_int()?;
+                    lhs = self.int_op(lhs, "+", rhs)?;
+                }
+                "-" => {
+                    self.next();
+                    let rhs = self.term_int()?;
+                    lhs = self.int_op(lhs, "-", rhs)?;
+                }
+                _ => break,
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn term_int(&mut self) -> Result<i64> {
+        let mut lhs = self.nested(Self::factor_int)?;
+
+        while let Some(op) = self.peek().and_then(|t| Self::mul_op(&t.val)) {
+            self.next();
+            let rhs = self.nested(Self::factor_int)?;
+            if op != "*" && rhs == 0 {
+                return Err(ParseError::Syntax("division by zero".into(), self.here()));
+            }
+            lhs = self.int_op(lhs, op, rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    fn factor_int(&mut self) -> Result<i64> {
+        let tok = self.next().ok_or(ParseError::UnexpectedEOF)?;
+
+        match tok.typ {
+            TokType::Num => tok.val.parse::<i64>().map_err(|_| {
+                ParseError::InvalidToken {
+                    msg: format!("invalid integer: {}", tok.val),
+                    tok: tok.clone(),
+                }
+            }),
+            TokType::Id if self.peek().is_some_and(|t| t.val == "(") => {
+                let args = self.args(Self::expr_int)?;
+                let args: Vec<i128> = args.into_iter().map(i128::from).collect();
+                let val = self.call(&tok, &args)?;
+                self.int_result(val)
+            }
+            TokType::Id => {
+                let val = self.lookup(&tok.val)?;
+                if val.fract() != 0.0 || val < i64::MIN as f64 || val >= i64::MAX as f64 {
+                    return Err(ParseError::Syntax(format!("non-integer variable: {}", tok.val), tok.pos));
+                }
+                Ok(val as i64)
+            }
+            TokType::Op if tok.val == "-" => {
+                let val = self.nested(Self::factor_int)?;
+                self.int_op(0, "-", val)
+            }
+            TokType::Sym if tok.val == "(" => {
+                let val = self.expr_int()?;
+                self.expect(")")?;
+                Ok(val)
+            }
+            _ => Err(ParseError::InvalidToken {
+                msg: format!("unexpected token: {}", tok.val),
+                tok,
+            }),
+        }
+    }
+
+    fn overflow(&self) -> ParseError {
+        ParseError::Syntax("integer overflow".into(), self.here())
+    }
+
+    /// Evaluates with units: a unit name directly after a number (`50 cm`)
+    /// scales it to base units. Adding or subtracting mismatched dimensions
+    /// is an error; multiplying and dividing combine them. A trailing
+    /// `as unit` or `in unit` gives the value as a plain number in that unit,
+    /// so `1000 m as km` is `1`.
+    pub fn parse_quantity(&mut self) -> Result<Quantity> {
+        let q = self.expr_q()?;
+        if !self.peek().is_some_and(|t| t.val == "as" || t.val == "in") {
+            return Ok(q);
+        }
+
+        self.next();
+        let tok = self.next().ok_or(ParseError::UnexpectedEOF)?;
+        let Some((scale, dim)) = (self.units)(&tok.val) else {
+            return Err(ParseError::InvalidToken {
+                msg: format!("unknown unit: {}", tok.val),
+                tok,
+            });
+        };
+        if dim != q.dim {
+            return Err(ParseError::Syntax(format!(
+                "cannot convert {:?} to {}",
+                q.dim, tok.val
+            ), self.here()));
+        }
+        Ok(Quantity { val: q.val / scale, dim: [0; 3] })
+    }
+
+    /// Evaluates duration literals (`s`, `m`, `h`, `d`) to seconds, so
+    /// `1h + 30m` is `5400`. Bare numbers may scale a duration but cannot be
+    /// added to one.
+    pub fn parse_duration(&mut self) -> Result<f64> {
+        self.units = Self::duration_unit;
+        let res = self.expr_q();
+        self.units = Self::unit;
+
+        let q = res?;
+        if q.dim != [0, 0, 1] {
+            return Err(ParseError::Syntax("expected a duration".into(), self.here()));
+        }
+        Ok(q.val)
+    }
+
+    fn expr_q(&mut self) -> Result<Quantity> {
+        let mut lhs = self.term_q()?;
 
         while let Some(tok) = self.peek() {
-            match tok.val.as_str() {
+            let sign = match tok.val.as_ref() {
+                "+" => 1.0,
+                "-" => -1.0,
+                _ => break,
+            };
+            self.next();
+            let rhs = self.term_q()?;
+            if lhs.dim != rhs.dim {
+                return Err(ParseError::Syntax(format!(
+                    "incompatible units: {:?} and {:?}",
+                    lhs.dim, rhs.dim
+                ), self.here()));
+            }
+            lhs.val += sign * rhs.val;
+        }
+        Ok(lhs)
+    }
+
+    fn term_q(&mut self) -> Result<Quantity> {
+        let mut lhs = self.nested(Self::factor_q)?;
+
+        while let Some(tok) = self.peek() {
+            match tok.val.as_ref() {
                 "*" => {
                     self.next();
-                    lhs *= self.factor()?;
+                    let rhs = self.nested(Self::factor_q)?;
+                    lhs.val *= rhs.val;
+                    lhs.dim = self.combine_dims(lhs.dim, rhs.dim, i8::checked_add)?;
                 }
                 "/" => {
                     self.next();
-                    let rhs = self.factor()?;
-                    if rhs == 0.0 {
-                        return Err(ParseError::Syntax("division by zero".into()));
+                    let rhs = self.nested(Self::factor_q)?;
+                    if approx_eq(rhs.val, 0.0, self.zero_eps) {
+                        return Err(ParseError::Syntax("division by zero".into(), self.here()));
                     }
-                    lhs /= rhs;
+                    lhs.val /= rhs.val;
+                    lhs.dim = self.combine_dims(lhs.dim, rhs.dim, i8::checked_sub)?;
                 }
                 _ => break,
             }
@@ -157,27 +1134,88 @@ impl Prs {
         Ok(lhs)
     }
 
-    fn factor(&mut self) -> Result<f64> {
+    /// Applies `op` to each pair of unit exponents, failing instead of
+    /// overflowing `i8`.
+    fn combine_dims(&self, a: [i8; 3], b: [i8; 3], op: fn(i8, i8) -> Option<i8>) -> Result<[i8; 3]> {
+        let mut dim = [0; 3];
+        for ((d, a), b) in dim.iter_mut().zip(a).zip(b) {
+            *d = op(a, b)
+                .ok_or_else(|| ParseError::Syntax("unit exponent out of range".into(), self.here()))?;
+        }
+        Ok(dim)
+    }
+
+    fn factor_q(&mut self) -> Result<Quantity> {
         let tok = self.next().ok_or(ParseError::UnexpectedEOF)?;
-        
+
         match tok.typ {
-            TokType::Num => tok.val.parse::<f64>().map_err(|_| {
-                ParseError::InvalidToken(format!("invalid number: {}", tok.val))
-            }),
-            TokType::Id => {
-                let ctx = self.ctx.read();
-                ctx.syms
-                    .get(&tok.val)
-                    .copied()
-                    .ok_or_else(|| ParseError::Syntax(format!("undefined variable: {}", tok.val)))
+            TokType::Num => {
+                let val = tok.val.parse::<f64>().map_err(|_| {
+                    ParseError::InvalidToken {
+                        msg: format!("invalid number: {}", tok.val),
+                        tok: tok.clone(),
+                    }
+                })?;
+                let unit = self
+                    .peek()
+                    .filter(|t| t.typ == TokType::Id)
+                    .and_then(|t| (self.units)(&t.val));
+                match unit {
+                    Some((scale, dim)) => {
+                        self.next();
+                        Ok(Quantity { val: val * scale, dim })
+                    }
+                    None => Ok(Quantity { val, dim: [0; 3] }),
+                }
             }
-            _ => Err(ParseError::InvalidToken(format!(
-                "unexpected token: {}",
-                tok.val
-            ))),
+            TokType::Id => self.lookup(&tok.val).map(|val| Quantity { val, dim: [0; 3] }),
+            TokType::Op if tok.val == "-" => {
+                let q = self.nested(Self::factor_q)?;
+                Ok(Quantity { val: -q.val, ..q })
+            }
+            TokType::Sym if tok.val == "(" => {
+                let q = self.expr_q()?;
+                self.expect(")")?;
+                Ok(q)
+            }
+            _ => Err(ParseError::InvalidToken {
+                msg: format!("unexpected token: {}", tok.val),
+                tok,
+            }),
         }
     }
 
+    fn unit(name: &str) -> Option<Unit> {
+        Some(match name {
+            "mm" => (0.001, [1, 0, 0]),
+            "cm" => (0.01, [1, 0, 0]),
+            "m" => (1.0, [1, 0, 0]),
+            "km" => (1000.0, [1, 0, 0]),
+            "g" => (0.001, [0, 1, 0]),
+            "kg" => (1.0, [0, 1, 0]),
+            "ms" => (0.001, [0, 0, 1]),
+            "s" => (1.0, [0, 0, 1]),
+            "min" => (60.0, [0, 0, 1]),
+            "h" => (3600.0, [0, 0, 1]),
+            _ => return None,
+        })
+    }
+
+    fn duration_unit(name: &str) -> Option<Unit> {
+        Some(match name {
+            "s" => (1.0, [0, 0, 1]),
+            "m" => (60.0, [0, 0, 1]),
+            "h" => (3600.0, [0, 0, 1]),
+            "d" => (86400.0, [0, 0, 1]),
+            _ => return None,
+        })
+    }
+
+    /// Position of the last consumed token, where errors are reported.
+    fn here(&self) -> usize {
+        self.idx.checked_sub(1).map_or(self.offset, |i| self.toks[i].pos)
+    }
+
     fn peek(&self) -> Option<&Tok> {
         self.toks.get(self.idx)
     }
@@ -191,4 +1229,115 @@ impl Prs {
             None
         }
     }
-}
\ No newline at end of file
+}
+
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+pub fn approx_eq(a: f64, b: f64, eps: f64) -> bool {
+    (a - b).abs() <= eps
+}
+
+pub fn tokenize(input: &str) -> Vec<Tok> {
+    Prs::lex(input, &LexOptions::default()).into()
+}
+
+/// False if `input` has an unclosed `(`, `{` or `|`, or ends in an operator
+/// (including the word operators `mod`, `in` and `as`), `=`, `;` or `,`,
+/// meaning a REPL should read another line before parsing.
+pub fn is_complete(input: &str) -> bool {
+    let toks = tokenize(input);
+    let depth = toks.iter().fold(0i64, |d, t| match t.val() {
+        "{" | "(" => d + 1,
+        "}" | ")" => d - 1,
+        _ => d,
+    });
+    let bars = toks.iter().filter(|t| t.val() == "|").count();
+    let trailing = toks
+        .last()
+        .is_some_and(|t| match t.typ() {
+            TokType::Op => true,
+            TokType::Id => matches!(t.val(), "mod" | "in" | "as"),
+            _ => matches!(t.val(), "=" | "=>" | ";" | ","),
+        });
+    depth <= 0 && bars % 2 == 0 && !trailing
+}
+
+/// Joins token values with single spaces, e.g. `2+3 *  4` becomes
+/// `2 + 3 * 4`. Lexing the result yields the same tokens.
+pub fn detokenize(toks: &[Tok]) -> String {
+    toks.iter().map(Tok::val).collect::<Vec<_>>().join(" ")
+}
+
+/// Evaluates in parallel, in input order. Every expression reads the same
+/// `vars`; an assignment stays local to the expression making it.
+pub fn eval_batch(exprs: &[String], vars: &HashMap<String, f64>) -> Vec<Result<f64>> {
+    let ctx = Arc::new(RwLock::new(PrsCtx::with_vars(vars)));
+
+    exprs
+        .par_iter()
+        .map(|input| Prs::with_context(input, Arc::clone(&ctx)).eval_local())
+        .collect()
+}
+
+/// Evaluates `input` once per row of equal-length `columns`, each bound to
+/// its name. The input is tokenized once and reused for every row.
+pub fn eval_columns(input: &str, columns: &HashMap<String, &[f64]>) -> Result<Vec<f64>> {
+    let rows = columns.values().next().map_or(0, |c| c.len());
+    if let Some((name, col)) = columns.iter().find(|(_, c)| c.len() != rows) {
+        return Err(ParseError::Syntax(
+            format!("column {} has {} rows, expected {}", name, col.len(), rows),
+            0,
+        ));
+    }
+
+    let mut prs = Prs::new(input);
+    (0..rows)
+        .map(|i| {
+            let row = columns.iter().map(|(name, col)| (name.clone(), col[i])).collect();
+            prs.scopes.push(row);
+            prs.idx = 0;
+            prs.eval_local()
+        })
+        .collect()
+}
+
+/// Evaluates `input` with `vars` bound. Only a number is accepted, so a
+/// comparison (`a < b`) or list (`a, b`) is an error rather than `a`.
+pub fn parse_with_vars(input: &str, vars: &HashMap<String, f64>) -> Result<f64> {
+    let ctx = Arc::new(RwLock::new(PrsCtx::with_vars(vars)));
+    Prs::with_context(input, ctx).eval_local()
+}
+
+pub fn format_result(value: f64, precision: usize) -> String {
+    format_result_with(value, precision, f64::INFINITY)
+}
+
+/// Rounds to `precision` decimals and trims trailing zeros, switching to
+/// scientific notation once `|value| >= sci_threshold`.
+pub fn format_result_with(value: f64, precision: usize, sci_threshold: f64) -> String {
+    if !value.is_finite() {
+        return value.to_string();
+    }
+
+    let trim = |s: &str| {
+        if s.contains('.') {
+            s.trim_end_matches('0').trim_end_matches('.').to_string()
+        } else {
+            s.to_string()
+        }
+    };
+
+    if value != 0.0 && value.abs() >= sci_threshold {
+        let s = format!("{:.*e}", precision, value);
+        let (mantissa, exp) = s.split_once('e').unwrap();
+        return format!("{}e{}", trim(mantissa), exp);
+    }
+
+    let s = trim(&format!("{:.*}", precision, value));
+    if s == "-0" { "0".to_string() } else { s }
+}
@@ -0,0 +1,305 @@
+use graft::core::*;
+
+#[test]
+fn max_value_bytes() {
+    let c = Core::new(2);
+    assert!(c.cache_put("a", vec![1, 2, 3]));
+    c.set_max_value_bytes(2);
+    assert!(!c.cache_put("b", vec![1, 2, 3]));
+    assert!(!c.cache_put("a", vec![9, 9, 9]));
+    assert_eq!(c.cache_get("a"), Some(vec![1, 2, 3]));
+    assert_eq!(c.cache_get("b"), None);
+}
+
+#[test]
+fn start_needs_consumer() {
+    use std::sync::{Arc, Mutex};
+    let c = Core::new(1);
+    assert!(matches!(c.start(), Err(CoreError::NoConsumer)));
+    assert!(!c.is_running());
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let s2 = Arc::clone(&seen);
+    c.on_event(move |e| s2.lock().unwrap().push(format!("{:?}", e)));
+    c.start().unwrap();
+    c.stop().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec![
+            "Start",
+            "StateChanged { from: false, to: true }",
+            "Stop",
+            "StateChanged { from: true, to: false }"
+        ]
+    );
+}
+
+#[test]
+fn reported_error_reaches_handler() {
+    let c = Core::new(1);
+    let (tx, rx) = std::sync::mpsc::channel();
+    let tx = std::sync::Mutex::new(tx);
+    c.on_event(move |e| {
+        if let Event::Error(m) = e {
+            tx.lock().unwrap().send(m.clone()).unwrap();
+        }
+    });
+    c.report_error("boom").unwrap();
+    assert_eq!(
+        rx.recv_timeout(std::time::Duration::from_secs(1)).unwrap(),
+        "boom"
+    );
+}
+
+#[test]
+fn cache_stats() {
+    let c = Core::new(1);
+    c.cache_put("a", vec![1]);
+    c.cache_get("a");
+    c.cache_get("a");
+    c.cache_get("b");
+    assert_eq!(c.cache_stats(), CacheStats { hits: 2, misses: 1 });
+}
+
+#[test]
+fn error_variants() {
+    let c = Core::new(1);
+    assert!(matches!(c.submit(Event::Stop), Err(CoreError::NotRunning)));
+    c.on_event(|_| {});
+    c.start().unwrap();
+    assert!(matches!(c.start(), Err(CoreError::AlreadyRunning)));
+    c.submit(Event::Error("x".into())).unwrap();
+    c.submit_timeout(
+        Event::Error("x".into()),
+        std::time::Duration::from_millis(10),
+    )
+    .unwrap();
+}
+
+#[test]
+fn event_log_keeps_last_n() {
+    let c = Core::new(1);
+    c.on_event(|_| {});
+    c.set_event_log_capacity(3);
+    for i in 0..10 {
+        c.report_error(format!("e{i}")).unwrap();
+    }
+    let ev: Vec<String> = c
+        .recent_events()
+        .iter()
+        .map(|e| format!("{:?}", e))
+        .collect();
+    assert_eq!(ev, vec!["Error(\"e7\")", "Error(\"e8\")", "Error(\"e9\")"]);
+}
+
+#[test]
+fn scoped_cache() {
+    let core = Core::new(1);
+    let a = core.cache_scoped("a");
+    let b = core.cache_scoped("b");
+    assert!(a.put("k", vec![1]));
+    assert!(b.put("k", vec![2]));
+    assert_eq!(a.get("k"), Some(vec![1]));
+    assert_eq!(b.get("k"), Some(vec![2]));
+    a.clear();
+    assert_eq!(a.get("k"), None);
+    assert_eq!(b.get("k"), Some(vec![2]));
+    assert_eq!(core.cache_get("k"), None);
+}
+
+#[test]
+fn small_capacity() {
+    let core = Core::with_capacity(1, 2);
+    let (block_tx, block_rx) = std::sync::mpsc::channel::<()>();
+    let block_rx = std::sync::Mutex::new(block_rx);
+    core.on_event(move |_| {
+        let _ = block_rx.lock().unwrap().recv();
+    });
+    core.start().unwrap();
+    let mut full = false;
+    for _ in 0..3 {
+        if matches!(core.submit(Event::Stop), Err(CoreError::ChannelFull)) {
+            full = true;
+            break;
+        }
+    }
+    assert!(full);
+    drop(block_tx);
+}
+
+#[test]
+fn state_changed() {
+    let c = Core::new(1);
+    c.on_event(|_| {});
+    c.start().unwrap();
+    c.stop().unwrap();
+    let ev: Vec<String> = c
+        .recent_events()
+        .iter()
+        .map(|e| format!("{:?}", e))
+        .collect();
+    assert_eq!(
+        ev,
+        vec![
+            "Start",
+            "StateChanged { from: false, to: true }",
+            "Stop",
+            "StateChanged { from: true, to: false }"
+        ]
+    );
+}
+
+#[test]
+fn get_or_insert_once() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    let core = Core::new(1);
+    let calls = AtomicUsize::new(0);
+    std::thread::scope(|s| {
+        for _ in 0..16 {
+            s.spawn(|| {
+                let v = core.cache_get_or_insert_with("k", || {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    std::thread::sleep(std::time::Duration::from_millis(20));
+                    vec![7]
+                });
+                assert_eq!(v, vec![7]);
+            });
+        }
+    });
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert_eq!(core.cache_stats().misses, 1);
+    assert_eq!(core.cache_stats().hits, 15);
+}
+
+#[test]
+fn panicking_handler() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    let c = Core::with_capacity(1, 2);
+    let n = Arc::new(AtomicUsize::new(0));
+    let n2 = Arc::clone(&n);
+    c.on_event(move |_| {
+        n2.fetch_add(1, Ordering::SeqCst);
+        panic!("boom");
+    });
+    c.start().unwrap();
+    for _ in 0..5 {
+        c.submit_timeout(Event::Error("x".into()), std::time::Duration::from_secs(2))
+            .unwrap();
+    }
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    assert_eq!(n.load(Ordering::SeqCst), 7);
+}
+
+#[test]
+fn report_without_consumer() {
+    let c = Core::with_capacity(1, 0);
+    assert!(matches!(c.report_error("x"), Err(CoreError::NoConsumer)));
+    let c = Core::new(1);
+    for _ in 0..2000 {
+        assert!(matches!(c.report_error("x"), Err(CoreError::NoConsumer)));
+    }
+    c.stop().unwrap();
+}
+
+#[test]
+fn handler_reads_log_while_channel_full() {
+    use std::sync::Arc;
+    use std::time::Duration;
+    let c = Arc::new(Core::with_capacity(1, 1));
+    let w = Arc::downgrade(&c);
+    c.on_event(move |_| {
+        if let Some(c) = w.upgrade() {
+            c.recent_events();
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    });
+    for _ in 0..20 {
+        let _ = c.start();
+        let _ = c.submit_timeout(Event::Error("x".into()), Duration::from_millis(20));
+        let _ = c.stop();
+    }
+}
+
+#[test]
+fn handler_reads_state_on_start() {
+    use std::sync::{Arc, Weak};
+    let c = Arc::new(Core::with_capacity(1, 4));
+    let w: Weak<Core> = Arc::downgrade(&c);
+    let (tx, rx) = std::sync::mpsc::channel();
+    let tx = std::sync::Mutex::new(tx);
+    c.on_event(move |e| {
+        if matches!(e, Event::Start) {
+            if let Some(c) = w.upgrade() {
+                tx.lock().unwrap().send(c.is_running()).unwrap();
+            }
+        }
+    });
+    let c2 = Arc::clone(&c);
+    let h = std::thread::spawn(move || c2.start());
+    assert!(rx.recv_timeout(std::time::Duration::from_secs(2)).unwrap());
+    h.join().unwrap().unwrap();
+}
+
+#[test]
+fn concurrent_transitions_alternate() {
+    use std::sync::{Arc, Mutex};
+    let c = Arc::new(Core::new(1));
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let s2 = Arc::clone(&seen);
+    c.on_event(move |e| {
+        if let Event::StateChanged { from, to } = e {
+            s2.lock().unwrap().push((*from, *to));
+        }
+    });
+    let hs: Vec<_> = (0..4)
+        .map(|_| {
+            let c = Arc::clone(&c);
+            std::thread::spawn(move || {
+                for _ in 0..100 {
+                    let _ = c.start();
+                    let _ = c.stop();
+                }
+            })
+        })
+        .collect();
+    for h in hs {
+        h.join().unwrap();
+    }
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    let seen = seen.lock().unwrap();
+    for (i, &(from, to)) in seen.iter().enumerate() {
+        assert_eq!((from, to), (i % 2 == 1, i % 2 == 0));
+    }
+    assert_eq!(seen.last().map(|t| t.1), Some(c.is_running()));
+}
+
+#[test]
+fn handler_watchdog() {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    let c = Core::new(1);
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let s2 = Arc::clone(&seen);
+    c.on_event(move |e| {
+        if matches!(e, Event::Start) {
+            std::thread::sleep(Duration::from_millis(300));
+        }
+        s2.lock().unwrap().push(format!("{:?}", e));
+    });
+    c.set_handler_timeout(Duration::from_millis(50));
+    c.start().unwrap();
+    std::thread::sleep(Duration::from_millis(150));
+    assert_eq!(c.handler_timeouts(), 1);
+    assert!(c
+        .recent_events()
+        .iter()
+        .any(|e| matches!(e, Event::Error(m) if m == "handler timeout")));
+    std::thread::sleep(Duration::from_millis(400));
+    assert_eq!(c.handler_timeouts(), 1);
+    assert!(seen
+        .lock()
+        .unwrap()
+        .contains(&"Error(\"handler timeout\")".to_string()));
+    c.stop().unwrap();
+}
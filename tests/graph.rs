@@ -0,0 +1,1094 @@
+use graft::algorithms::*;
+
+#[test]
+fn transpose() {
+    let mut g = Grf::new();
+    for i in 1..=3 {
+        g.add_nd(i, i as f64, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 1.0).unwrap();
+    g.add_ed(2, 3, 2.0).unwrap();
+    let t = g.transpose();
+    assert_eq!(t.neighbors(3), vec![(2, 2.0)]);
+    assert_eq!(t.neighbors(2), vec![(1, 1.0)]);
+    assert_eq!(t.transpose().to_adjacency_list(), g.to_adjacency_list());
+}
+
+#[test]
+fn flow_twice() {
+    let mut g = Grf::new();
+    for i in 1..=4 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 3.0).unwrap();
+    g.add_ed(1, 3, 2.0).unwrap();
+    g.add_ed(2, 4, 2.0).unwrap();
+    g.add_ed(3, 4, 3.0).unwrap();
+    let a = g.max_flow(1, 4);
+    let b = g.max_flow(1, 4);
+    assert_eq!(a, 4.0);
+    assert_eq!(a, b);
+}
+
+#[test]
+fn star_centrality() {
+    let mut g = Grf::new();
+    for i in 0..5 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    for i in 1..5 {
+        g.add_ed(0, i, 1.0).unwrap();
+    }
+    let c = g.degree_centrality(false);
+    assert_eq!(c[0], (0, 1.0));
+    assert_eq!(c[1], (1, 0.25));
+    let w = g.degree_centrality(true);
+    assert_eq!(w[0].0, 0);
+}
+
+#[test]
+fn forest() {
+    let mut g = Grf::new();
+    for i in 1..=5 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 1.0).unwrap();
+    g.add_ed(3, 4, 2.0).unwrap();
+    g.add_ed(4, 5, 3.0).unwrap();
+    assert_eq!(g.mst().len(), 1);
+    assert_eq!(
+        g.minimum_spanning_forest(),
+        vec![(1, 2, 1.0), (3, 4, 2.0), (4, 5, 3.0)]
+    );
+    // With every edge mirrored and the graph connected, both give the same weight.
+    let mut s = Grf::new();
+    for i in 1..=4 {
+        s.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    for (u, v, w) in [
+        (1, 2, 4.0),
+        (2, 3, 1.0),
+        (3, 4, 2.0),
+        (1, 4, 3.0),
+        (1, 3, 5.0),
+    ] {
+        s.add_ed(u, v, w).unwrap();
+        s.add_ed(v, u, w).unwrap();
+    }
+    let total = |es: Vec<(usize, usize, f64)>| es.iter().map(|e| e.2).sum::<f64>();
+    assert_eq!(total(s.mst()), 6.0);
+    assert_eq!(total(s.minimum_spanning_forest()), 6.0);
+}
+
+#[test]
+fn eq_hash() {
+    let mut a = Grf::new();
+    for i in 1..=3 {
+        a.add_nd(i, i as f64, 0.0, 0.0).unwrap();
+    }
+    a.add_ed(1, 2, 1.0).unwrap();
+    a.add_ed(2, 3, 2.0).unwrap();
+    let mut b = Grf::new();
+    for i in (1..=3).rev() {
+        b.add_nd(i, i as f64, 0.0, 0.0).unwrap();
+    }
+    b.add_ed(2, 3, 2.0).unwrap();
+    b.add_ed(1, 2, 1.0).unwrap();
+    assert!(a == b);
+    assert_eq!(a.structural_hash(), b.structural_hash());
+    assert!(a.transpose() != a);
+    assert!(a.transpose().transpose() == a);
+}
+
+#[test]
+fn shortest_path_tree() {
+    let mut g = Grf::new();
+    for i in 1..=5 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 1.0).unwrap();
+    g.add_ed(1, 3, 4.0).unwrap();
+    g.add_ed(2, 3, 1.0).unwrap();
+    g.add_ed(3, 4, 1.0).unwrap();
+    let t = g.shortest_path_tree(1);
+    assert_eq!(t[&1], (0.0, None));
+    assert_eq!(t[&3], (2.0, Some(2)));
+    assert_eq!(t[&4], (3.0, Some(3)));
+    assert!(!t.contains_key(&5));
+}
+
+#[test]
+fn layout() {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    for seed in 0..10 {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let n = 20;
+        let mut g = Grf::new();
+        let mut pos = Vec::new();
+        for i in 0..n {
+            let (x, y) = (rng.gen_range(0.0..100.0), rng.gen_range(0.0..100.0));
+            g.add_nd(i, 0.0, x, y).unwrap();
+            pos.push((x, y));
+        }
+        let edges: Vec<(usize, usize)> = (0..n - 1)
+            .map(|i| (i, i + 1))
+            .chain([(0, 10), (5, 15)])
+            .collect();
+        for &(u, v) in &edges {
+            g.add_ed(u, v, 1.0).unwrap();
+        }
+        let pairs: Vec<(usize, usize)> = (0..n)
+            .flat_map(|u| (u + 1..n).map(move |v| (u, v)))
+            .collect();
+        let mean = |d: Vec<f64>| d.iter().sum::<f64>() / d.len() as f64;
+        let spread = mean(
+            pairs
+                .iter()
+                .map(|&(u, v)| {
+                    ((pos[u].0 - pos[v].0).powi(2) + (pos[u].1 - pos[v].1).powi(2)).sqrt()
+                })
+                .collect(),
+        );
+
+        g.layout_force_directed(200);
+        // Reverse edges weighted by the new distances read back the layout.
+        for &(u, v) in &pairs {
+            g.add_ed_geo(v, u).unwrap();
+        }
+        let dist = |&(u, v): &(usize, usize)| g.edge_weight(v, u).unwrap();
+        let linked = mean(edges.iter().map(dist).collect());
+        assert!(linked < spread, "{seed}: {linked} >= {spread}");
+        assert!(linked < mean(pairs.iter().map(dist).collect()), "{seed}");
+    }
+}
+
+#[test]
+fn postman() {
+    // square 1-2-3-4-1 plus diagonal 1-3: odd vertices 1 and 3 -> duplicate diagonal (cheapest path 1-3 = 2)
+    let mut g = Grf::new();
+    for i in 1..=4 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 1.0).unwrap();
+    g.add_ed(2, 3, 1.0).unwrap();
+    g.add_ed(3, 4, 1.0).unwrap();
+    g.add_ed(4, 1, 1.0).unwrap();
+    g.add_ed(1, 3, 3.0).unwrap();
+    let (cost, walk) = g.chinese_postman().unwrap();
+    assert_eq!(cost, 7.0 + 2.0);
+    assert_eq!(walk.len(), 8);
+    assert_eq!(walk.first(), walk.last());
+    // euler graph: triangle
+    let mut t = Grf::new();
+    for i in 1..=3 {
+        t.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    t.add_ed(1, 2, 1.0).unwrap();
+    t.add_ed(2, 3, 1.0).unwrap();
+    t.add_ed(3, 1, 1.0).unwrap();
+    assert_eq!(t.chinese_postman().unwrap().0, 3.0);
+    t.add_nd(4, 0.0, 0.0, 0.0).unwrap();
+    t.add_nd(5, 0.0, 0.0, 0.0).unwrap();
+    t.add_ed(4, 5, 1.0).unwrap();
+    assert!(t.chinese_postman().is_none());
+}
+
+#[test]
+fn neighbors() {
+    let mut g = Grf::new();
+    for i in 1..=4 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 1.5).unwrap();
+    g.add_ed(1, 3, 2.5).unwrap();
+    g.add_ed(4, 1, 3.5).unwrap();
+    let mut n = g.neighbors(1);
+    n.sort_by_key(|x| x.0);
+    assert_eq!(n, vec![(2, 1.5), (3, 2.5)]);
+    assert_eq!(g.in_neighbors(1), vec![(4, 3.5)]);
+}
+
+#[test]
+fn contains() {
+    let mut g = Grf::new();
+    for i in 1..=2 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 1.0).unwrap();
+    assert!(g.contains_node(1) && !g.contains_node(9));
+    assert!(g.contains_edge(1, 2) && !g.contains_edge(2, 1) && !g.contains_edge(1, 9));
+}
+
+#[test]
+fn betweenness() {
+    let mut g = Grf::new();
+    for i in 1..=5 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    for i in 1..5 {
+        g.add_ed(i, i + 1, 1.0).unwrap();
+        g.add_ed(i + 1, i, 1.0).unwrap();
+    }
+    let b = g.betweenness_centrality(false, false);
+    assert_eq!(b[&3], 8.0);
+    assert_eq!(b[&2], 6.0);
+    assert_eq!(b[&1], 0.0);
+    let w = g.betweenness_centrality(true, false);
+    assert_eq!(w, b);
+    // diamond: two equal paths 1->2->4, 1->3->4
+    let mut d = Grf::new();
+    for i in 1..=4 {
+        d.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    d.add_ed(1, 2, 1.0).unwrap();
+    d.add_ed(1, 3, 1.0).unwrap();
+    d.add_ed(2, 4, 1.0).unwrap();
+    d.add_ed(3, 4, 1.0).unwrap();
+    assert_eq!(d.betweenness_centrality(true, false)[&2], 0.5);
+    assert_eq!(d.betweenness_centrality(false, false)[&2], 0.5);
+    let mut p = Grf::new();
+    for i in 0..3 {
+        p.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    p.add_ed(0, 1, 1.0).unwrap();
+    p.add_ed(1, 2, 1.0).unwrap();
+    p.add_ed(1, 1, 0.0).unwrap();
+    assert_eq!(p.betweenness_centrality(true, false)[&1], 1.0);
+    assert_eq!(p.betweenness_centrality(false, false)[&1], 1.0);
+}
+
+#[test]
+fn multi_edges() {
+    let mut g = Grf::new();
+    for i in 1..=2 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 3.0).unwrap();
+    assert!(!g.has_multi_edges());
+    g.add_ed(1, 2, 1.0).unwrap();
+    assert!(g.has_multi_edges());
+    let s = g.to_simple();
+    assert!(!s.has_multi_edges());
+    assert_eq!(s.neighbors(1), vec![(2, 1.0)]);
+}
+
+#[test]
+fn sparsify() {
+    let mut g = Grf::new();
+    for i in 0..30 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    for i in 0..30 {
+        for j in 0..30 {
+            if i != j {
+                g.add_ed(i, j, 1.0 + (i * j % 7) as f64).unwrap();
+            }
+        }
+    }
+    assert!(g.sparsify(1.0, 3, false) == g);
+    assert!(g.sparsify(1.0, 3, true) == g);
+    let a = g.sparsify(0.3, 42, false);
+    let b = g.sparsify(0.3, 42, false);
+    assert!(a == b);
+    let c = g.sparsify(0.3, 42, true);
+    let n = |x: &Grf| (0..30).map(|i| x.neighbors(i).len()).sum::<usize>();
+    assert!(n(&a) < 870 && n(&a) > 150);
+    assert!(n(&c) < 870);
+}
+
+#[test]
+fn incremental_mst() {
+    let mut g = Grf::new();
+    for i in 1..=3 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 5.0).unwrap();
+    g.add_ed(2, 3, 1.0).unwrap();
+    assert_eq!(g.mst_add_edge(1, 3, 2.0).unwrap(), Some((1, 2, 5.0)));
+    assert_eq!(g.mst_add_edge(1, 2, 9.0).unwrap(), None);
+    assert_eq!(g.mst_add_edge(3, 1, 1.5).unwrap(), Some((1, 3, 2.0)));
+    assert_eq!(g.mst_add_edge(2, 2, 0.1).unwrap(), None);
+}
+
+#[test]
+fn relabel() {
+    let mut g = Grf::new();
+    for i in [10, 20, 30] {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(10, 20, 1.0).unwrap();
+    g.add_ed(20, 30, 2.0).unwrap();
+    g.relabel(|i| i / 10 - 1).unwrap();
+    assert_eq!(g.neighbors(0), vec![(1, 1.0)]);
+    assert_eq!(g.neighbors(1), vec![(2, 2.0)]);
+    assert!(matches!(g.relabel(|_| 7), Err(GraphError::DuplicateId(7))));
+    assert!(g.contains_node(2));
+}
+
+#[test]
+fn bfs_path() {
+    let mut g = Grf::new();
+    for i in 1..=5 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 100.0).unwrap();
+    g.add_ed(2, 4, 100.0).unwrap();
+    g.add_ed(1, 3, 1.0).unwrap();
+    g.add_ed(3, 5, 1.0).unwrap();
+    g.add_ed(5, 4, 1.0).unwrap();
+    assert_eq!(g.shortest_path_unweighted(1, 4), Some(vec![1, 2, 4]));
+    assert_eq!(g.shortest_path_unweighted(1, 1), Some(vec![1]));
+    assert_eq!(g.shortest_path_unweighted(4, 1), None);
+}
+
+#[test]
+fn triangles() {
+    let mut g = Grf::new();
+    for i in 0..4 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    for i in 0..4 {
+        for j in i + 1..4 {
+            g.add_ed(i, j, 1.0).unwrap();
+        }
+    }
+    assert_eq!(g.count_triangles(), 4);
+    let mut t = Grf::new();
+    for i in 0..3 {
+        t.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    t.add_ed(0, 1, 1.0).unwrap();
+    t.add_ed(1, 2, 1.0).unwrap();
+    t.add_ed(2, 0, 1.0).unwrap();
+    t.add_ed(0, 2, 1.0).unwrap();
+    t.add_ed(0, 0, 1.0).unwrap();
+    assert_eq!(t.count_triangles(), 1);
+}
+
+#[test]
+fn min_cut() {
+    // two triangles (heavy) joined by a light bridge of weight 1
+    let mut g = Grf::new();
+    for i in 1..=6 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 5.0).unwrap();
+    g.add_ed(2, 3, 5.0).unwrap();
+    g.add_ed(3, 1, 5.0).unwrap();
+    g.add_ed(4, 5, 5.0).unwrap();
+    g.add_ed(5, 6, 5.0).unwrap();
+    g.add_ed(6, 4, 5.0).unwrap();
+    g.add_ed(3, 4, 1.0).unwrap();
+    let (c, side) = g.global_min_cut();
+    assert_eq!(c, 1.0);
+    assert!(side == vec![1, 2, 3] || side == vec![4, 5, 6]);
+    // wikipedia example value 4
+    let mut h = Grf::new();
+    for i in 1..=8 {
+        h.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    for (a, b, w) in [
+        (1, 2, 2.0),
+        (1, 5, 3.0),
+        (2, 3, 3.0),
+        (2, 5, 2.0),
+        (2, 6, 2.0),
+        (3, 4, 4.0),
+        (3, 7, 2.0),
+        (4, 7, 2.0),
+        (4, 8, 2.0),
+        (5, 6, 3.0),
+        (6, 7, 1.0),
+        (7, 8, 3.0),
+    ] {
+        h.add_ed(a, b, w).unwrap();
+    }
+    let (c, side) = h.global_min_cut();
+    assert_eq!(c, 4.0);
+    assert_eq!(side.len(), 4);
+}
+
+#[test]
+fn subgraph() {
+    let mut g = Grf::new();
+    for i in 1..=5 {
+        g.add_nd(i, i as f64, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 1.0).unwrap();
+    g.add_ed(2, 3, 1.0).unwrap();
+    g.add_ed(3, 1, 1.0).unwrap();
+    g.add_ed(3, 4, 1.0).unwrap();
+    g.add_ed(4, 5, 1.0).unwrap();
+    let s = g.subgraph(&[1, 2, 3, 99].into_iter().collect());
+    assert_eq!(s.count_triangles(), 1);
+    assert!(!s.contains_node(4) && !s.contains_node(99));
+    assert!(s.neighbors(3) == vec![(1, 1.0)]);
+}
+
+#[test]
+fn connected() {
+    let mut g = Grf::new();
+    for i in 1..=6 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 1.0).unwrap();
+    g.add_ed(3, 2, 1.0).unwrap();
+    g.add_ed(3, 4, 1.0).unwrap();
+    g.add_ed(5, 6, 1.0).unwrap();
+    assert!(g.connected(1, 4));
+    assert!(!g.connected(1, 5));
+    g.add_ed(4, 5, 1.0).unwrap();
+    assert!(g.connected(1, 6));
+    assert!(!g.connected(1, 99));
+}
+
+#[test]
+fn mst_constrained() {
+    let mut g = Grf::new();
+    for i in 1..=3 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 1.0).unwrap();
+    g.add_ed(2, 3, 1.0).unwrap();
+    g.add_ed(3, 1, 5.0).unwrap();
+    let t = g.mst_constrained(&[], &[]).unwrap();
+    assert_eq!(t.iter().map(|e| e.2).sum::<f64>(), 2.0);
+    let t = g.mst_constrained(&[(1, 3)], &[]).unwrap();
+    assert!(t.contains(&(3, 1, 5.0)));
+    assert_eq!(t.len(), 2);
+    assert!(g.mst_constrained(&[], &[(1, 2), (2, 3)]).is_none());
+    assert!(g.mst_constrained(&[(1, 2), (2, 3), (1, 3)], &[]).is_none());
+}
+
+#[test]
+fn assortativity() {
+    let mut g = Grf::new();
+    for i in 0..5 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    for i in 1..5 {
+        g.add_ed(0, i, 1.0).unwrap();
+    }
+    assert!((g.degree_assortativity() + 1.0).abs() < 1e-9);
+    let mut c = Grf::new();
+    for i in 0..4 {
+        c.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    for i in 0..4 {
+        c.add_ed(i, (i + 1) % 4, 1.0).unwrap();
+    }
+    assert_eq!(c.degree_assortativity(), 0.0);
+}
+
+#[test]
+fn center() {
+    let mut g = Grf::new();
+    for i in 1..=5 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    for i in 1..5 {
+        g.add_ed(i, i + 1, 1.0).unwrap();
+        g.add_ed(i + 1, i, 1.0).unwrap();
+    }
+    assert_eq!(g.center(), vec![3]);
+    assert_eq!(g.eccentricity(1), 4.0);
+    g.add_nd(6, 0.0, 0.0, 0.0).unwrap();
+    assert!(g.center().is_empty());
+}
+
+#[test]
+fn independent_set() {
+    let mut g = Grf::new();
+    for i in 0..6 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    for &(a, b) in &[(0, 1), (0, 2), (0, 3), (1, 2), (3, 4), (4, 5)] {
+        g.add_ed(a, b, 1.0).unwrap();
+    }
+    let s = g.max_independent_set_greedy();
+    for &a in &s {
+        for &b in &s {
+            assert!(!g.contains_edge(a, b));
+        }
+    }
+    assert_eq!(s.len(), 3);
+}
+
+#[test]
+fn critical_path() {
+    let mut g = Grf::new();
+    for i in 1..=5 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 3.0).unwrap();
+    g.add_ed(1, 3, 2.0).unwrap();
+    g.add_ed(2, 4, 4.0).unwrap();
+    g.add_ed(3, 4, 1.0).unwrap();
+    g.add_ed(3, 5, 9.0).unwrap();
+    g.add_ed(4, 5, 2.0).unwrap();
+    assert_eq!(g.longest_path_dag(1, 5), Some((11.0, vec![1, 3, 5])));
+    assert_eq!(g.longest_path_dag(1, 4), Some((7.0, vec![1, 2, 4])));
+    assert_eq!(g.longest_path_dag(5, 1), None);
+    g.add_ed(5, 1, 1.0).unwrap();
+    assert_eq!(g.longest_path_dag(1, 5), None);
+}
+
+#[test]
+fn undirected() {
+    let mut g = Grf::new();
+    for i in 1..=3 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 4.0).unwrap();
+    g.add_ed(2, 1, 3.0).unwrap();
+    g.add_ed(2, 3, 1.0).unwrap();
+    assert!(g.shortest_path_unweighted(3, 1).is_none());
+    let u = g.as_undirected();
+    assert_eq!(u.shortest_path_unweighted(3, 1), Some(vec![3, 2, 1]));
+    assert_eq!(u.neighbors(1), vec![(2, 3.0)]);
+    assert_eq!(u.neighbors(2).len(), 2);
+    assert!(!u.has_multi_edges());
+}
+
+#[test]
+fn edge_flows() {
+    let mut g = Grf::new();
+    for i in 1..=4 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 3.0).unwrap();
+    g.add_ed(1, 3, 2.0).unwrap();
+    g.add_ed(2, 4, 2.0).unwrap();
+    g.add_ed(3, 4, 3.0).unwrap();
+    let f = g.max_flow(1, 4);
+    let out: f64 = g
+        .edge_flows()
+        .iter()
+        .filter(|e| e.0 == 1)
+        .map(|e| e.2)
+        .sum();
+    assert_eq!(out, f);
+}
+
+#[test]
+fn adjacency_list() {
+    let mut g = Grf::new();
+    for i in 1..=3 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 2.5).unwrap();
+    let adj = g.to_adjacency_list();
+    assert_eq!(adj.len(), 3);
+    assert_eq!(adj[&1], vec![(2, 2.5)]);
+    assert!(adj[&2].is_empty());
+    assert!(adj[&3].is_empty());
+}
+
+#[test]
+fn row_normalize() {
+    let mut g = Grf::new();
+    for i in 1..=3 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 3.0).unwrap();
+    g.add_ed(1, 3, 1.0).unwrap();
+    g.add_ed(2, 3, 7.0).unwrap();
+    g.row_normalize();
+    for i in 1..=2 {
+        let s: f64 = g.neighbors(i).iter().map(|e| e.1).sum();
+        assert!((s - 1.0).abs() < 1e-12);
+    }
+    assert_eq!(g.neighbors(1), vec![(3, 0.25), (2, 0.75)]);
+    assert!(g.neighbors(3).is_empty());
+}
+
+#[test]
+fn nodes_where() {
+    let mut g = Grf::new();
+    for i in 1..=5 {
+        g.add_nd(i, i as f64 * 10.0, 0.0, 0.0).unwrap();
+    }
+    assert_eq!(g.nodes_where(|_, v| v > 25.0), vec![3, 4, 5]);
+    assert_eq!(g.nodes_where(|id, v| id % 2 == 0 && v < 45.0), vec![2, 4]);
+}
+
+#[test]
+fn cancellation() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::{Duration, Instant};
+    let mut g = Grf::new();
+    let n = 1500;
+    for i in 0..n {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    for i in 0..n {
+        g.add_ed(i, (i + 1) % n, 1.0).unwrap();
+        g.add_ed(i, (i * 7 + 3) % n, 1.0).unwrap();
+    }
+    let flag = AtomicBool::new(false);
+    let t = Instant::now();
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            std::thread::sleep(Duration::from_millis(20));
+            flag.store(true, Ordering::Relaxed);
+        });
+        assert!(matches!(
+            g.betweenness_centrality_cancellable(false, false, &flag),
+            Err(GraphError::Cancelled)
+        ));
+    });
+    assert!(t.elapsed() < Duration::from_secs(2));
+    assert!(matches!(
+        g.center_cancellable(&flag),
+        Err(GraphError::Cancelled)
+    ));
+    let mut small = Grf::new();
+    for i in 1..=3 {
+        small.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    small.add_ed(1, 2, 1.0).unwrap();
+    small.add_ed(2, 3, 1.0).unwrap();
+    assert_eq!(small.betweenness_centrality(false, false)[&2], 1.0);
+}
+
+#[test]
+fn topo_layers() {
+    let mut g = Grf::new();
+    for i in 1..=5 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 1.0).unwrap();
+    g.add_ed(1, 3, 1.0).unwrap();
+    g.add_ed(2, 4, 1.0).unwrap();
+    g.add_ed(3, 4, 1.0).unwrap();
+    g.add_ed(1, 4, 1.0).unwrap();
+    g.add_ed(5, 4, 1.0).unwrap();
+    assert_eq!(
+        g.topo_layers().unwrap(),
+        vec![vec![1, 5], vec![2, 3], vec![4]]
+    );
+    g.add_ed(4, 1, 1.0).unwrap();
+    assert!(matches!(g.topo_layers(), Err(GraphError::Cycle)));
+}
+
+#[test]
+fn similarity() {
+    let mut g = Grf::new();
+    for i in 1..=6 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    for &(a, b, w) in &[
+        (1, 3, 1.0),
+        (1, 4, 2.0),
+        (2, 3, 1.0),
+        (2, 4, 2.0),
+        (5, 6, 1.0),
+        (6, 4, 4.0),
+    ] {
+        g.add_ed(a, b, w).unwrap();
+    }
+    assert_eq!(g.neighbor_similarity(1, 2, false), 1.0);
+    assert_eq!(g.neighbor_similarity(1, 2, true), 1.0);
+    assert_eq!(g.neighbor_similarity(1, 5, false), 0.0);
+    assert_eq!(g.neighbor_similarity(1, 6, false), 0.5);
+    assert_eq!(g.neighbor_similarity(1, 6, true), 2.0 / 5.0);
+    assert_eq!(g.neighbor_similarity(3, 4, true), 0.0);
+}
+
+#[test]
+fn map_weights() {
+    let mut g = Grf::new();
+    for i in 1..=3 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 2.0).unwrap();
+    g.add_ed(2, 3, 5.0).unwrap();
+    g.map_weights(|w| -w);
+    assert_eq!(g.edge_weight(1, 2), Some(-2.0));
+    assert_eq!(g.edge_weight(2, 3), Some(-5.0));
+    assert_eq!(g.edge_weight(3, 1), None);
+    assert_eq!(g.edge_weight(9, 1), None);
+}
+
+#[test]
+fn weight_stats() {
+    let mut g = Grf::new();
+    assert_eq!(g.weight_stats(), WeightStats::default());
+    for i in 1..=3 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 2.0).unwrap();
+    g.add_ed(2, 3, 4.0).unwrap();
+    g.add_ed(1, 3, 4.0).unwrap();
+    g.add_ed(3, 1, 6.0).unwrap();
+    let s = g.weight_stats();
+    assert_eq!(s.count, 4);
+    assert_eq!(s.mean, 4.0);
+    assert_eq!(s.max, 6.0);
+    assert_eq!(s.min, 2.0);
+    assert_eq!(s.median, 4.0);
+    assert!((s.std_dev - 2.0f64.sqrt()).abs() < 1e-12);
+}
+
+#[test]
+fn union_find() {
+    let mut uf = UnionFind::new(5);
+    assert!(!uf.connected(0, 1));
+    assert!(uf.union(0, 1));
+    assert!(uf.union(1, 2));
+    assert!(!uf.union(0, 2));
+    assert!(uf.connected(0, 2));
+    assert_eq!(uf.find(2), uf.find(0));
+    assert!(!uf.connected(3, 4));
+}
+
+#[test]
+fn geo_edge() {
+    let mut g = Grf::new();
+    g.add_nd(1, 0.0, 1.0, 1.0).unwrap();
+    g.add_nd(2, 0.0, 4.0, 5.0).unwrap();
+    g.add_ed_geo(1, 2).unwrap();
+    assert_eq!(g.edge_weight(1, 2), Some(5.0));
+}
+
+#[test]
+fn self_loops() {
+    let mut g = Grf::new();
+    for i in 1..=3 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 1, 0.1).unwrap();
+    g.add_ed(1, 2, 1.0).unwrap();
+    g.add_ed(2, 3, 1.0).unwrap();
+    g.add_ed(3, 3, 0.1).unwrap();
+    assert!(g.mst().iter().all(|e| e.0 != e.1));
+    assert!(g.minimum_spanning_forest().iter().all(|e| e.0 != e.1));
+    assert_eq!(g.mst_constrained(&[], &[]).unwrap().len(), 2);
+    assert_eq!(g.degree_centrality(false)[0], (1, 3.0 / 2.0));
+    assert_eq!(g.shortest_path_tree(1)[&1], (0.0, None));
+    g.remove_self_loops();
+    assert!(!g.contains_edge(1, 1) && !g.contains_edge(3, 3));
+    assert!(g.contains_edge(1, 2) && g.contains_edge(2, 3));
+    assert_eq!(g.mst_add_edge(3, 3, 0.0).unwrap(), None);
+}
+
+#[test]
+fn dfs_events() {
+    let mut g = Grf::new();
+    for i in 1..=4 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(1, 2, 1.0).unwrap();
+    g.add_ed(2, 3, 1.0).unwrap();
+    g.add_ed(3, 1, 1.0).unwrap();
+    g.add_ed(1, 4, 1.0).unwrap();
+    let mut ev = Vec::new();
+    g.dfs_visit(1, |u, e| ev.push((u, e)));
+    assert!(ev.contains(&(3, VisitEvent::BackEdge(1))));
+    assert_eq!(
+        ev.iter()
+            .filter(|e| matches!(e.1, VisitEvent::BackEdge(_)))
+            .count(),
+        1
+    );
+    assert_eq!(ev.iter().filter(|e| e.1 == VisitEvent::Discover).count(), 4);
+    assert_eq!(ev.iter().filter(|e| e.1 == VisitEvent::Finish).count(), 4);
+    assert_eq!(ev.last(), Some(&(1, VisitEvent::Finish)));
+    let mut dag = Grf::new();
+    for i in 1..=3 {
+        dag.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    dag.add_ed(1, 2, 1.0).unwrap();
+    dag.add_ed(2, 3, 1.0).unwrap();
+    dag.add_ed(1, 3, 1.0).unwrap();
+    let mut back = 0;
+    dag.dfs_visit(1, |_, e| {
+        if let VisitEvent::BackEdge(_) = e {
+            back += 1
+        }
+    });
+    assert_eq!(back, 0);
+}
+
+#[test]
+fn flow_scaling() {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    let mut rng = StdRng::seed_from_u64(3);
+    for _ in 0..200 {
+        let n = rng.gen_range(2..8);
+        let mut g = Grf::new();
+        for i in 0..n {
+            g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+        }
+        for _ in 0..rng.gen_range(0..16) {
+            let (a, b) = (rng.gen_range(0..n), rng.gen_range(0..n));
+            if a != b && !g.contains_edge(a, b) {
+                g.add_ed(a, b, rng.gen_range(1..1000) as f64).unwrap();
+            }
+        }
+        let basic = g.max_flow(0, n - 1);
+        let scaled = g.max_flow_scaling(0, n - 1);
+        assert!((scaled - basic).abs() < 1e-9);
+        let out: f64 = g
+            .edge_flows()
+            .iter()
+            .filter(|e| e.0 == 0)
+            .map(|e| e.2)
+            .sum::<f64>()
+            - g.edge_flows()
+                .iter()
+                .filter(|e| e.1 == 0)
+                .map(|e| e.2)
+                .sum::<f64>();
+        assert!((out - scaled).abs() < 1e-9);
+    }
+}
+
+#[test]
+fn flow_scaling_multi() {
+    let mut g = Grf::new();
+    for i in 0..3 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    g.add_ed(0, 1, 3.0).unwrap();
+    g.add_ed(0, 1, 4.0).unwrap();
+    g.add_ed(1, 1, 9.0).unwrap();
+    g.add_ed(1, 2, 0.5).unwrap();
+    g.add_ed(1, 2, 10.0).unwrap();
+    assert_eq!(g.max_flow_scaling(0, 2), 7.0);
+    assert_eq!(g.max_flow_scaling(0, 0), 0.0);
+}
+
+#[test]
+fn contraction_hierarchy() {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    let mut rng = StdRng::seed_from_u64(11);
+    for _ in 0..30 {
+        let n = rng.gen_range(1..40);
+        let mut g = Grf::new();
+        for i in 0..n {
+            g.add_nd(i * 3, 0.0, 0.0, 0.0).unwrap();
+        }
+        for _ in 0..rng.gen_range(0..n * 4) {
+            g.add_ed(
+                rng.gen_range(0..n) * 3,
+                rng.gen_range(0..n) * 3,
+                rng.gen_range(0..20) as f64,
+            )
+            .unwrap();
+        }
+        let ch = g.build_contraction_hierarchy();
+        for s in 0..n {
+            let sp = g.shortest_path_tree(s * 3);
+            for t in 0..n {
+                assert_eq!(
+                    ch.query(s * 3, t * 3),
+                    sp.get(&(t * 3)).map(|x| x.0),
+                    "{s} {t}"
+                );
+            }
+        }
+    }
+    assert_eq!(Grf::new().build_contraction_hierarchy().query(0, 1), None);
+}
+
+#[test]
+fn limits() {
+    let mut g = Grf::with_limits(2, 1);
+    g.add_nd(1, 0.0, 0.0, 0.0).unwrap();
+    g.add_nd(2, 0.0, 0.0, 0.0).unwrap();
+    assert!(matches!(
+        g.add_nd(3, 0.0, 0.0, 0.0),
+        Err(GraphError::NodeLimit(2))
+    ));
+    assert!(g.contains_node(2) && !g.contains_node(3));
+    g.add_ed(1, 2, 1.0).unwrap();
+    assert!(matches!(g.add_ed(2, 1, 1.0), Err(GraphError::EdgeLimit(1))));
+    assert!(g.mst_add_edge(2, 1, 1.0).is_err());
+}
+
+#[test]
+fn generators() {
+    assert_eq!(Grf::complete(4).edge_count(), 12);
+    assert_eq!(Grf::cycle(5).edge_count(), 5);
+    assert_eq!(Grf::path(5).edge_count(), 4);
+    assert_eq!(Grf::path(0).node_count(), 0);
+    assert_eq!(Grf::cycle(1).edge_count(), 1);
+    let a = Grf::random(20, 0.3, 7).to_adjacency_list();
+    let b = Grf::random(20, 0.3, 7).to_adjacency_list();
+    assert_eq!(a, b);
+    assert_eq!(Grf::random(10, 1.0, 1).edge_count(), 90);
+    assert_eq!(Grf::random(10, 0.0, 1).edge_count(), 0);
+}
+
+#[test]
+fn edge_disjoint() {
+    let mut g = Grf::new();
+    for i in 1..=5 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    for (u, v) in [(1, 2), (2, 5), (1, 3), (3, 4), (4, 5), (2, 3)] {
+        g.add_ed(u, v, 9.0).unwrap();
+    }
+    let mut p = g.edge_disjoint_paths(1, 5);
+    p.sort();
+    assert_eq!(p, vec![vec![1, 2, 5], vec![1, 3, 4, 5]]);
+    assert!(g.edge_disjoint_paths(5, 1).is_empty());
+    assert!(g.edge_disjoint_paths(1, 1).is_empty());
+    assert!(g.edge_disjoint_paths(1, 99).is_empty());
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    let mut rng = StdRng::seed_from_u64(5);
+    for seed in 0..100 {
+        let n = rng.gen_range(2..12);
+        let g = Grf::random(n, 0.4, seed);
+        let (a, b) = (rng.gen_range(0..n), rng.gen_range(0..n));
+        let paths = g.edge_disjoint_paths(a, b);
+        let mut edges = std::collections::HashSet::new();
+        for p in &paths {
+            assert_eq!((p[0], *p.last().unwrap()), (a, b));
+            let mut nodes = std::collections::HashSet::new();
+            assert!(p.iter().all(|x| nodes.insert(*x)));
+            for w in p.windows(2) {
+                assert!(g.neighbors(w[0]).iter().any(|x| x.0 == w[1]));
+                assert!(edges.insert((w[0], w[1])));
+            }
+        }
+        if a != b {
+            let mut unit = Grf::new();
+            for i in 0..n {
+                unit.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+            }
+            for (u, l) in g.to_adjacency_list() {
+                for (v, _) in l {
+                    unit.add_ed(u, v, 1.0).unwrap();
+                }
+            }
+            assert_eq!(paths.len() as f64, unit.max_flow_scaling(a, b));
+        }
+    }
+}
+
+#[test]
+fn diameter_approx() {
+    assert_eq!(Grf::path(10).diameter_approx(3, 1), 9.0);
+    assert_eq!(Grf::path(10).as_undirected().diameter_approx(1, 4), 9.0);
+    assert_eq!(Grf::new().diameter_approx(5, 1), 0.0);
+    assert_eq!(Grf::cycle(6).diameter_approx(2, 2), 5.0);
+    for seed in 0..20 {
+        let g = Grf::random(15, 0.2, seed);
+        let exact = (0..15)
+            .flat_map(|i| g.shortest_path_tree(i).into_values().map(|x| x.0))
+            .fold(0.0, f64::max);
+        assert!(g.diameter_approx(4, seed) <= exact + 1e-12);
+    }
+}
+
+#[test]
+fn bit_adjacency() {
+    for seed in 0..10 {
+        let g = Grf::random(150, 0.3, seed);
+        let b = g.to_bit_adjacency();
+        for u in 0..150 {
+            let mut want: Vec<usize> = g.neighbors(u).into_iter().map(|x| x.0).collect();
+            want.sort();
+            want.dedup();
+            assert_eq!(b.neighbors(u), want);
+            for v in [0, 63, 64, 149] {
+                assert_eq!(b.has_edge(u, v), want.contains(&v));
+                let c = b.common_neighbors(u, v);
+                assert_eq!(c.len(), b.common_count(u, v));
+                assert!(c.iter().all(|x| want.contains(x) && b.has_edge(v, *x)));
+            }
+        }
+        assert!(b.neighbors(999).is_empty());
+        assert_eq!(b.common_count(999, 1), 0);
+    }
+}
+
+#[test]
+fn connectivity_timeline() {
+    // Two routes 0-1-3 and 0-2-3; connectivity breaks on the third deletion.
+    let mut g = Grf::new();
+    for i in 0..5 {
+        g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+    }
+    for (u, v) in [(0, 1), (1, 3), (0, 2), (2, 3), (3, 2)] {
+        g.add_ed(u, v, 1.0).unwrap();
+    }
+    assert_eq!(
+        g.connectivity_timeline(0, 3, &[(1, 3), (4, 0), (2, 3), (0, 2), (1, 3)]),
+        vec![true, true, false, false, false]
+    );
+    assert_eq!(
+        g.connectivity_timeline(0, 3, &[(3, 1), (1, 3)]),
+        vec![true, true]
+    );
+    assert_eq!(g.connectivity_timeline(0, 9, &[(0, 1)]), vec![false]);
+    assert!(g.connected(1, 3));
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    let mut rng = StdRng::seed_from_u64(3);
+    for seed in 0..50 {
+        let n = rng.gen_range(2..10);
+        let g = Grf::random(n, 0.3, seed);
+        let dels: Vec<(usize, usize)> = (0..8)
+            .map(|_| (rng.gen_range(0..n), rng.gen_range(0..n)))
+            .collect();
+        let got = g.connectivity_timeline(0, n - 1, &dels);
+        for k in 0..dels.len() {
+            let gone: std::collections::HashSet<_> = dels[..=k]
+                .iter()
+                .map(|&(a, b)| (a.min(b), a.max(b)))
+                .collect();
+            let mut h = Grf::new();
+            for i in 0..n {
+                h.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+            }
+            for (u, l) in g.to_adjacency_list() {
+                for (v, w) in l {
+                    if !gone.contains(&(u.min(v), u.max(v))) {
+                        h.add_ed(u, v, w).unwrap();
+                    }
+                }
+            }
+            assert_eq!(got[k], h.connected(0, n - 1), "{seed} {k}");
+        }
+    }
+}
+
+#[test]
+fn forest_undirected() {
+    let mut g = Grf::new();
+    g.add_nd(0, 0.0, 0.0, 0.0).unwrap();
+    g.add_nd(1, 0.0, 0.0, 0.0).unwrap();
+    g.add_ed(1, 0, 2.0).unwrap();
+    assert_eq!(g.minimum_spanning_forest(), vec![(1, 0, 2.0)]);
+    assert_eq!(g.mst_add_edge(0, 1, 5.0).unwrap(), None);
+    assert_eq!(g.mst_add_edge(0, 1, 1.0).unwrap(), Some((1, 0, 2.0)));
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    let mut rng = StdRng::seed_from_u64(2);
+    for seed in 0..50 {
+        let g = Grf::random(rng.gen_range(1..12), 0.2, seed);
+        let f: f64 = g.minimum_spanning_forest().iter().map(|e| e.2).sum();
+        if let Some(t) = g.mst_constrained(&[], &[]) {
+            assert!((f - t.iter().map(|e| e.2).sum::<f64>()).abs() < 1e-9);
+        }
+    }
+}
+
+#[test]
+fn postman_cap() {
+    for leaves in [20, 30, 64, 70] {
+        let mut g = Grf::new();
+        for i in 0..=leaves {
+            g.add_nd(i, 0.0, 0.0, 0.0).unwrap();
+        }
+        for i in 1..=leaves {
+            g.add_ed(0, i, 1.0).unwrap();
+        }
+        let r = g.chinese_postman();
+        // Star with even leaves: all leaves odd (+ center if leaves odd).
+        assert_eq!(r.is_some(), leaves <= 20, "{leaves}");
+    }
+}
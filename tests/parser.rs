@@ -0,0 +1,822 @@
+use graft::core::parser::*;
+use proptest::prelude::*;
+use std::collections::HashMap;
+
+#[test]
+fn batch_reports_errors_by_index() {
+    let mut vars = HashMap::new();
+    vars.insert("x".to_string(), 2.0);
+    let exprs: Vec<String> = vec!["1 + x".into(), "2 *".into(), "x * x".into()];
+    let out = eval_batch(&exprs, &vars);
+    assert_eq!(out[0].as_ref().unwrap(), &3.0);
+    assert!(out[1].is_err());
+    assert_eq!(out[2].as_ref().unwrap(), &4.0);
+    let out = eval_batch(&["1 2".into(), "3 )".into(), "4".into()], &vars);
+    assert!(matches!(out[0], Err(ParseError::InvalidToken { .. })));
+    assert!(matches!(out[1], Err(ParseError::InvalidToken { .. })));
+    assert_eq!(out[2].as_ref().unwrap(), &4.0);
+    assert!(matches!(
+        Prs::new("2 3").parse(),
+        Err(ParseError::InvalidToken { .. })
+    ));
+    assert!(Prs::new("1 < 2 3").parse().is_err());
+}
+
+#[test]
+fn int_parse() {
+    assert_eq!(Prs::new("7 / 2").parse_int().unwrap(), 3);
+    assert_eq!(Prs::new("2 + 3 * 4").parse_int().unwrap(), 14);
+    assert!(Prs::new("9223372036854775807 + 1").parse_int().is_err());
+    assert!(Prs::new("3000000000 * 4000000000").parse_int().is_err());
+    assert!(Prs::new("1.5 + 1").parse_int().is_err());
+    assert!(Prs::new("1 / 0").parse_int().is_err());
+}
+
+#[test]
+fn lex_opts() {
+    let opts = LexOptions {
+        ident_chars: vec!['.', '$'],
+    };
+    assert_eq!(
+        Prs::with_options("order.total", &opts)
+            .parse_f64()
+            .unwrap_err()
+            .to_string(),
+        "syntax error: undefined variable: order.total"
+    );
+    assert_eq!(
+        Prs::new("order.total").parse_f64().unwrap_err().to_string(),
+        "syntax error: undefined variable: order"
+    );
+    let t = tokenize("order.total");
+    assert_eq!(
+        t.iter().map(|t| t.val()).collect::<Vec<_>>(),
+        vec!["order", "total"]
+    );
+}
+
+#[test]
+fn token_values() {
+    let toks = tokenize("x1 = 2.5 * (y - 3); |z|");
+    let vals: Vec<&str> = toks.iter().map(|t| t.val()).collect();
+    assert_eq!(
+        vals,
+        ["x1", "=", "2.5", "*", "(", "y", "-", "3", ")", ";", "|", "z", "|"]
+    );
+    assert_eq!(toks[3].val(), String::from("*"));
+}
+
+#[test]
+fn tok_types() {
+    for tok in tokenize("a + 1 ;") {
+        let want = match tok.typ() {
+            TokType::Id => "a",
+            TokType::Op => "+",
+            TokType::Num => "1",
+            TokType::Sym => ";",
+            _ => unreachable!(),
+        };
+        assert_eq!(tok.val(), want);
+    }
+}
+
+#[test]
+fn abs() {
+    assert_eq!(Prs::new("|-5|").parse_f64().unwrap(), 5.0);
+    assert_eq!(Prs::new("|3-7|").parse_f64().unwrap(), 4.0);
+    assert_eq!(Prs::new("||0-3| - 5|").parse_f64().unwrap(), 2.0);
+    assert_eq!(Prs::new("2 * |1 - 4| + 1").parse_f64().unwrap(), 7.0);
+    assert!(Prs::new("|1 - 4").parse_f64().is_err());
+}
+
+#[test]
+fn units() {
+    let q = Prs::new("3 m + 50 cm").parse_quantity().unwrap();
+    assert!((q.val - 3.5).abs() < 1e-12);
+    assert_eq!(q.dim, [1, 0, 0]);
+    let q = Prs::new("2 m * 3 m / 4 s").parse_quantity().unwrap();
+    assert_eq!(q.dim, [2, 0, -1]);
+    assert!((q.val - 1.5).abs() < 1e-12);
+    assert!(Prs::new("3 m + 2 s").parse_quantity().is_err());
+    let q = Prs::new("2 * 3 km").parse_quantity().unwrap();
+    assert_eq!(q.val, 6000.0);
+    assert!(Prs::new(&["1 m"; 130].join(" * "))
+        .parse_quantity()
+        .is_err());
+    assert!(Prs::new(&["1 m"; 140].join(" / "))
+        .parse_quantity()
+        .is_err());
+    assert!(Prs::new(&["1h"; 130].join(" * ")).parse_duration().is_err());
+}
+
+#[test]
+fn blocks() {
+    let mut p = Prs::new("x = 2; { y = 3; x * y }");
+    assert_eq!(p.parse_f64().unwrap(), 6.0);
+    let mut p = Prs::new("x = 2; { y = 3 }; y");
+    assert!(p.parse_f64().is_err());
+    assert_eq!(Prs::new("x = 2; { x = 5 }; x").parse_f64().unwrap(), 2.0);
+    assert_eq!(
+        Prs::new("a = 1; { b = 2; { a + b } }").parse_f64().unwrap(),
+        3.0
+    );
+    assert!(Prs::new("{ 1 + 2").parse_f64().is_err());
+}
+
+#[test]
+fn deadline() {
+    use std::time::{Duration, Instant};
+    let input = vec!["1"; 200_000].join(" + ");
+    let err = Prs::new(&input)
+        .parse_with_deadline(Instant::now() + Duration::from_nanos(1))
+        .unwrap_err();
+    assert_eq!(err.to_string(), "syntax error: parse deadline exceeded");
+    assert_eq!(
+        Prs::new("1 + 2")
+            .parse_with_deadline(Instant::now() + Duration::from_secs(5))
+            .unwrap(),
+        3.0
+    );
+}
+
+#[test]
+fn durations() {
+    assert_eq!(Prs::new("1h + 30m").parse_duration().unwrap(), 5400.0);
+    assert_eq!(Prs::new("2 * 1d - 1s").parse_duration().unwrap(), 172799.0);
+    assert!(Prs::new("1h + 5").parse_duration().is_err());
+    assert!(Prs::new("5").parse_duration().is_err());
+    let q = Prs::new("1 m").parse_quantity().unwrap();
+    assert_eq!(q.dim, [1, 0, 0]);
+}
+
+#[test]
+fn shared_ctx() {
+    use parking_lot::RwLock;
+    use std::sync::Arc;
+    let ctx = Arc::new(RwLock::new(PrsCtx::new()));
+    ctx.write().set("k", 21.0);
+    let hs: Vec<_> = (0..2)
+        .map(|_| {
+            let c = Arc::clone(&ctx);
+            std::thread::spawn(move || Prs::with_context("k * 2", c).parse_f64().unwrap())
+        })
+        .collect();
+    for h in hs {
+        assert_eq!(h.join().unwrap(), 42.0);
+    }
+    Prs::with_context("z = 3", Arc::clone(&ctx))
+        .parse_f64()
+        .unwrap();
+    assert_eq!(ctx.read().get("z"), Some(3.0));
+}
+
+#[test]
+fn with_vars() {
+    let vars: HashMap<String, f64> = [("a", 2.0), ("b", 3.0), ("c", 4.0)]
+        .iter()
+        .map(|(k, v)| (k.to_string(), *v))
+        .collect();
+    assert_eq!(parse_with_vars("a*b + c", &vars).unwrap(), 10.0);
+    assert!(parse_with_vars("a < b", &vars).is_err());
+    assert!(parse_with_vars("a, b", &vars).is_err());
+}
+
+#[test]
+fn trace() {
+    let (v, t) = Prs::new("2 + 3 * 4").parse_trace().unwrap();
+    assert_eq!(v, 14.0);
+    assert_eq!(t, vec!["3 * 4 = 12", "2 + 12 = 14"]);
+}
+
+#[test]
+fn format() {
+    assert_eq!(format_result(0.1 + 0.2, 10), "0.3");
+    assert_eq!(format_result(2.50, 3), "2.5");
+    assert_eq!(format_result(2.0, 3), "2");
+    assert_eq!(format_result(1.23456, 2), "1.23");
+    assert_eq!(format_result(-0.0001, 2), "0");
+    assert_eq!(format_result(100.0, 0), "100");
+    assert_eq!(format_result_with(1234567.0, 3, 1e6), "1.235e6");
+    assert_eq!(format_result_with(1000000.0, 3, 1e6), "1e6");
+    assert_eq!(format_result_with(999.0, 3, 1e6), "999");
+}
+
+#[test]
+fn render_caret() {
+    let input = "1 + * 2";
+    let err = Prs::new(input).parse_f64().unwrap_err();
+    assert_eq!(err.render(input), "1 + * 2\n    ^");
+    let input = "b = 1;\n\tb + zz";
+    let err = Prs::new(input).parse_f64().unwrap_err();
+    assert_eq!(err.pos(), Some(12));
+    assert_eq!(err.render(input), "\tb + zz\n\t    ^");
+    let input = "1 +";
+    let err = Prs::new(input).parse_f64().unwrap_err();
+    assert_eq!(err.render(input), "1 +\n   ^");
+}
+
+#[test]
+fn resolver() {
+    let mut p = Prs::new("x * 2 + y");
+    p.set_var("y", 1.0);
+    p.set_resolver(std::sync::Arc::new(|name| (name == "x").then_some(20.0)));
+    assert_eq!(p.parse_f64().unwrap(), 41.0);
+    let mut p = Prs::new("z");
+    p.set_resolver(std::sync::Arc::new(|_| None));
+    assert!(p.parse_f64().is_err());
+}
+
+#[test]
+fn detok() {
+    let toks = tokenize("2+3 *  4");
+    let s = detokenize(&toks);
+    assert_eq!(s, "2 + 3 * 4");
+    let again = tokenize(&s);
+    assert_eq!(
+        toks.iter().map(|t| t.typ().clone()).collect::<Vec<_>>(),
+        again.iter().map(|t| t.typ().clone()).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        detokenize(&tokenize("x=-1;{y=|x|}")),
+        "x = - 1 ; { y = | x | }"
+    );
+}
+
+#[test]
+fn int_modes() {
+    let src = "9223372036854775807 + 1";
+    assert!(Prs::new(src).parse_int().is_err());
+    let mut p = Prs::new(src);
+    p.set_int_mode(IntMode::Wrapping);
+    assert_eq!(p.parse_int().unwrap(), i64::MIN);
+    let mut p = Prs::new(src);
+    p.set_int_mode(IntMode::Saturating);
+    assert_eq!(p.parse_int().unwrap(), i64::MAX);
+    let mut p = Prs::new("7 / 2 * 3 - 1");
+    p.set_int_mode(IntMode::Saturating);
+    assert_eq!(p.parse_int().unwrap(), 8);
+    let mut p = Prs::new("1 / 0");
+    p.set_int_mode(IntMode::Wrapping);
+    assert!(p.parse_int().is_err());
+}
+
+#[test]
+fn reset() {
+    let mut p = Prs::new("x = 4");
+    p.parse_f64().unwrap();
+    p.reset("x * 2");
+    assert_eq!(p.parse_f64().unwrap(), 8.0);
+}
+
+#[test]
+fn ans() {
+    let mut p = Prs::new("ans");
+    assert_eq!(p.parse_f64().unwrap(), 0.0);
+    p.reset("3 + 4");
+    p.parse_f64().unwrap();
+    p.reset("ans * 2");
+    assert_eq!(p.parse_f64().unwrap(), 14.0);
+}
+
+#[test]
+fn decimal_points() {
+    assert!(Prs::new("2 + .").parse_f64().is_err());
+    for src in ["1.2.3", "..5", "1..0"] {
+        assert!(
+            matches!(
+                Prs::new(src).parse_f64(),
+                Err(ParseError::InvalidToken { .. })
+            ),
+            "{src}"
+        );
+    }
+    assert_eq!(Prs::new(".5 + 1.").parse_f64().unwrap(), 1.5);
+    assert!(Prs::new(&"9".repeat(500)).parse_f64().is_ok());
+    assert!(Prs::new(&"9".repeat(50)).parse_int().is_err());
+}
+
+#[test]
+fn complete() {
+    assert!(!is_complete("(1 +"));
+    assert!(is_complete("(1 + 2)"));
+    assert!(!is_complete("{ x = 1;"));
+    assert!(!is_complete("7 mod"));
+    assert!(!is_complete("let x = 3 in"));
+    assert!(!is_complete("1000 m as"));
+    assert!(is_complete("7 mod 2"));
+    assert!(!is_complete("x ="));
+    assert!(!is_complete("case { x > 1 =>"));
+    assert!(!is_complete("1,"));
+    assert!(!is_complete("|x - 2"));
+    assert!(!is_complete("x ="));
+    assert!(is_complete("{ x = 1 } + |x|"));
+    assert!(is_complete(""));
+}
+
+#[test]
+fn near_zero() {
+    let src = "1 / (0.000000000000000001)";
+    assert!(Prs::new(src).parse_f64().is_ok());
+    let mut p = Prs::new(src);
+    p.set_zero_tolerance(1e-12);
+    assert!(p.parse_f64().is_err());
+    let mut p = Prs::new("1 m / 0.0000000000001 s");
+    p.set_zero_tolerance(1e-12);
+    assert!(p.parse_quantity().is_err());
+    assert!(approx_eq(0.1 + 0.2, 0.3, 1e-12));
+    assert!(!approx_eq(0.1 + 0.2, 0.3, 0.0));
+}
+
+#[test]
+fn undefined_zero() {
+    assert!(Prs::new("x + 5").parse_f64().is_err());
+    let mut p = Prs::new("x + 5");
+    p.set_undefined_as_zero(true);
+    assert_eq!(p.parse_f64().unwrap(), 5.0);
+}
+
+#[test]
+fn structured_invalid() {
+    match Prs::new("1 + * 2").parse_f64() {
+        Err(ParseError::InvalidToken { tok, msg }) => {
+            assert_eq!(tok.val(), "*");
+            assert_eq!(tok.pos(), 4);
+            assert_eq!(*tok.typ(), TokType::Op);
+            assert_eq!(msg, "unexpected token: *");
+        }
+        other => panic!("{:?}", other.map(|_| ())),
+    }
+    assert_eq!(
+        Prs::new("1.2.3").parse_f64().unwrap_err().to_string(),
+        "invalid token sequence: invalid number: 1.2.3"
+    );
+}
+
+#[test]
+fn tuple() {
+    assert_eq!(
+        Prs::new("1, 2+3, 4").parse_tuple().unwrap(),
+        vec![1.0, 5.0, 4.0]
+    );
+    assert_eq!(Prs::new("7").parse_tuple().unwrap(), vec![7.0]);
+    assert!(Prs::new("1,").parse_tuple().is_err());
+}
+
+#[test]
+fn offset() {
+    let err = Prs::with_offset("1 + * 2", 100).parse_f64().unwrap_err();
+    assert_eq!(err.pos(), Some(104));
+    let err = Prs::with_offset("zz", 10).parse_f64().unwrap_err();
+    assert_eq!(err.pos(), Some(10));
+    let mut p = Prs::with_offset("1", 7);
+    p.reset("2 / 0");
+    assert_eq!(p.parse_f64().unwrap_err().pos(), Some(11));
+}
+
+#[test]
+fn observer() {
+    use std::sync::{Arc, Mutex};
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let s2 = Arc::clone(&seen);
+    let mut p = Prs::new("1+2+3");
+    p.set_observer(Arc::new(move |e, v| {
+        s2.lock().unwrap().push((e.to_string(), v))
+    }));
+    assert_eq!(p.parse_f64().unwrap(), 6.0);
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec![("1 + 2".to_string(), 3.0), ("3 + 3".to_string(), 6.0)]
+    );
+}
+
+#[test]
+fn floor_div_mod() {
+    assert_eq!(Prs::new("7 // 2").parse_f64().unwrap(), 3.0);
+    assert_eq!(Prs::new("0 - 7 // 2").parse_f64().unwrap(), -3.0);
+    assert_eq!(Prs::new("-7 // 2").parse_f64().unwrap(), -4.0);
+    assert_eq!(Prs::new("-7 mod 2").parse_f64().unwrap(), 1.0);
+    assert_eq!(Prs::new("7 mod -2").parse_f64().unwrap(), -1.0);
+    assert_eq!(Prs::new("7.5 mod 2").parse_f64().unwrap(), 1.5);
+    assert_eq!(Prs::new("7 // 2").parse_int().unwrap(), 3);
+    assert_eq!(Prs::new("7 / 2 * 2").parse_int().unwrap(), 6);
+    assert!(Prs::new("1 // 0").parse_f64().is_err());
+    assert!(Prs::new("1 mod 0").parse_int().is_err());
+    let (v, t) = Prs::new("9 mod 4 * 2").parse_trace().unwrap();
+    assert_eq!(v, 2.0);
+    assert_eq!(t, vec!["9 mod 4 = 1", "1 * 2 = 2"]);
+    assert_eq!(detokenize(&tokenize("7//2")), "7 // 2");
+}
+
+#[test]
+fn floor_int_neg() {
+    for (src, want) in [
+        ("a // 2", -4),
+        ("a mod 2", 1),
+        ("7 mod b", -1),
+        ("7 // b", -4),
+        ("a // b", 3),
+        ("a mod b", -1),
+    ] {
+        let mut p = Prs::new(src);
+        p.set_var("a", -7.0);
+        p.set_var("b", -2.0);
+        assert_eq!(p.parse_int().unwrap(), want, "{src}");
+    }
+}
+
+#[test]
+fn separators_hex() {
+    assert_eq!(Prs::new("1_000").parse_f64().unwrap(), 1000.0);
+    assert_eq!(
+        Prs::new("1_000_000 + 0.000_5").parse_f64().unwrap(),
+        1000000.0005
+    );
+    assert_eq!(Prs::new("1_000").parse_int().unwrap(), 1000);
+    for src in [
+        "1__0", "1_", "1_.5", "1._5", "0x", "0x1.2.3", "0x1p", "0x_1",
+    ] {
+        assert!(
+            matches!(
+                Prs::new(src).parse_f64(),
+                Err(ParseError::InvalidToken { .. })
+            ),
+            "{src}"
+        );
+    }
+    assert_eq!(Prs::new("0x1.8p3").parse_f64().unwrap(), 12.0);
+    assert_eq!(Prs::new("0x1p-2").parse_f64().unwrap(), 0.25);
+    assert_eq!(Prs::new("0xff + 1").parse_f64().unwrap(), 256.0);
+    assert_eq!(Prs::new("0XFF_FF").parse_int().unwrap(), 65535);
+    assert_eq!(
+        Prs::new("0x7fffffffffffffff").parse_int().unwrap(),
+        i64::MAX
+    );
+    assert_eq!(Prs::new("0x.8").parse_f64().unwrap(), 0.5);
+    assert_eq!(Prs::new("0x10 - 2").parse_f64().unwrap(), 14.0);
+}
+
+#[test]
+fn eval_columns_rows() {
+    let a = [1.0, 2.0, 3.0];
+    let b = [10.0, 20.0, 30.0];
+    let mut cols: HashMap<String, &[f64]> = HashMap::new();
+    cols.insert("a".into(), &a);
+    cols.insert("b".into(), &b);
+    assert_eq!(
+        eval_columns("a + b * 2", &cols).unwrap(),
+        vec![21.0, 42.0, 63.0]
+    );
+    let short = [1.0];
+    cols.insert("c".into(), &short);
+    assert!(eval_columns("a + b", &cols).is_err());
+    assert_eq!(
+        eval_columns("1", &HashMap::new()).unwrap(),
+        Vec::<f64>::new()
+    );
+}
+
+#[test]
+fn unit_conversion() {
+    let q = Prs::new("1000 m as km").parse_quantity().unwrap();
+    assert_eq!((q.val, q.dim), (1.0, [0, 0, 0]));
+    let q = Prs::new("90 min in h").parse_quantity().unwrap();
+    assert_eq!(q.val, 1.5);
+    assert!(Prs::new("1 m in s").parse_quantity().is_err());
+    assert!(Prs::new("1 m in").parse_quantity().is_err());
+    assert!(Prs::new("1 m as parsec").parse_quantity().is_err());
+    assert_eq!(Prs::new("2 m + 3 m").parse_quantity().unwrap().val, 5.0);
+}
+
+#[test]
+fn case_expr() {
+    let src = "case { x < 0 => -1; x > 0 => 1; _ => 0 }";
+    for (x, want) in [(-5.0, -1.0), (3.0, 1.0), (0.0, 0.0)] {
+        let mut p = Prs::new(src);
+        p.set_var("x", x);
+        assert_eq!(p.parse_f64().unwrap(), want);
+    }
+    // Skipped branches are not evaluated.
+    assert_eq!(
+        Prs::new("case { 1 => 2; _ => 1 / 0 }").parse_f64().unwrap(),
+        2.0
+    );
+    assert_eq!(
+        Prs::new("case { 0 => undefinedvar; 2 >= 2 => 7; }")
+            .parse_f64()
+            .unwrap(),
+        7.0
+    );
+    assert_eq!(
+        Prs::new("case { 1 != 1 => 1; _ => case { 3 == 3 => 9; _ => {5} } } + 1")
+            .parse_f64()
+            .unwrap(),
+        10.0
+    );
+    assert!(Prs::new("case { 0 => 1 }").parse_f64().is_err());
+    assert!(Prs::new("case { 1 => 1").parse_f64().is_err());
+    assert!(!is_complete("case { x =>"));
+}
+
+#[test]
+fn let_in() {
+    let mut p = Prs::new("let x = 3 in x*x");
+    assert_eq!(p.parse_f64().unwrap(), 9.0);
+    p.reset("x");
+    assert!(p.parse_f64().is_err());
+    assert_eq!(
+        Prs::new("let x = 1 in let x = x + 1 in x * 10")
+            .parse_f64()
+            .unwrap(),
+        20.0
+    );
+    assert_eq!(
+        Prs::new("let a = 2 in { let a = 5 in a } + a")
+            .parse_f64()
+            .unwrap(),
+        7.0
+    );
+    assert!(Prs::new("let x 3 in x").parse_f64().is_err());
+    assert!(Prs::new("let x = 3 x").parse_f64().is_err());
+    // Without a name after it, `let` is an ordinary variable.
+    assert_eq!(Prs::new("let = 4; let").parse_f64().unwrap(), 4.0);
+}
+
+#[test]
+fn int_negation() {
+    assert_eq!(Prs::new("-5").parse_int().unwrap(), -5);
+    assert_eq!(Prs::new("3 * -2 - -1").parse_int().unwrap(), -5);
+    let mut p = Prs::new("-x");
+    p.set_var("x", -9223372036854775807.0 - 1.0);
+    assert!(p.parse_int().is_err());
+    p.reset("-x");
+    p.set_int_mode(IntMode::Saturating);
+    assert_eq!(p.parse_int().unwrap(), i64::MAX);
+    p.reset("-x");
+    p.set_int_mode(IntMode::Wrapping);
+    assert_eq!(p.parse_int().unwrap(), i64::MIN);
+}
+
+#[test]
+fn nesting_limit() {
+    for s in [
+        "-".repeat(5001) + "1",
+        "|".repeat(5001) + "1",
+        "{".repeat(5001) + "1",
+        "let x = 1 in ".repeat(5000) + "1",
+        "case { 1 => ".repeat(5000),
+    ] {
+        let e = Prs::new(&s).parse_f64().unwrap_err();
+        assert!(e.to_string().contains("nesting too deep"), "{e}");
+    }
+    assert!(Prs::new(&("-".repeat(5001) + "1")).parse_int().is_err());
+    assert!(Prs::new(&("-".repeat(5001) + "1"))
+        .parse_quantity()
+        .is_err());
+    assert_eq!(Prs::new(&("-".repeat(150) + "1")).parse_f64().unwrap(), 1.0);
+    let s = "{".repeat(60) + "2" + &"}".repeat(60);
+    assert_eq!(Prs::new(&s).parse_f64().unwrap(), 2.0);
+    let mut p = Prs::new(&("-".repeat(5001) + "1"));
+    assert!(p.parse_f64().is_err());
+    p.reset("--3");
+    assert_eq!(p.parse_f64().unwrap(), 3.0);
+}
+
+#[test]
+fn batch_isolated() {
+    let mut vars = HashMap::new();
+    vars.insert("x".to_string(), 1.0);
+    let exprs: Vec<String> = (0..400)
+        .map(|i| {
+            if i % 2 == 0 {
+                "x = 100".into()
+            } else {
+                "x + 1".into()
+            }
+        })
+        .collect();
+    let res = eval_batch(&exprs, &vars);
+    for (i, r) in res.into_iter().enumerate() {
+        assert_eq!(r.unwrap(), if i % 2 == 0 { 100.0 } else { 2.0 });
+    }
+    assert_eq!(
+        eval_batch(&["y = 3; y * 2".into()], &vars)[0]
+            .as_ref()
+            .unwrap(),
+        &6.0
+    );
+}
+
+#[test]
+fn batch_ans() {
+    let vars = HashMap::new();
+    for _ in 0..20 {
+        let r: Vec<f64> = eval_batch(&["7".into(), "ans".into()], &vars)
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(r, vec![7.0, 0.0]);
+    }
+    let a = [1.0, 2.0];
+    let mut cols: HashMap<String, &[f64]> = HashMap::new();
+    cols.insert("a".into(), &a);
+    assert_eq!(
+        eval_columns("t = a * 10; t + ans", &cols).unwrap(),
+        vec![10.0, 20.0]
+    );
+    assert_eq!(eval_columns("a = a + 1; a", &cols).unwrap(), vec![2.0, 3.0]);
+    assert_eq!(parse_with_vars("ans + 1", &vars).unwrap(), 1.0);
+}
+
+#[test]
+fn hex_exponent_bounds() {
+    assert!(Prs::new("0x1.8p-2147483648").parse_f64().is_err());
+    assert!(Prs::new("0x1p2147483647").parse_f64().is_err());
+    assert!(Prs::new("0x1p1101").parse_f64().is_err());
+    assert!(Prs::new("0x1p1024").parse_f64().is_err());
+    let toks = tokenize("0x1p1024 + 0x1p1023");
+    let again = tokenize(&detokenize(&toks));
+    assert_eq!(
+        toks.iter().map(|t| t.typ().clone()).collect::<Vec<_>>(),
+        again.iter().map(|t| t.typ().clone()).collect::<Vec<_>>()
+    );
+    assert_eq!(*again[0].typ(), TokType::Num);
+    assert_eq!(Prs::new("0x1.8p3").parse_f64().unwrap(), 12.0);
+    assert_eq!(
+        Prs::new("0x1p-1074").parse_f64().unwrap(),
+        f64::from_bits(1)
+    );
+    let long = format!("0x0.{}1p0", "0".repeat(3000));
+    assert_eq!(Prs::new(&long).parse_f64().unwrap(), 0.0);
+}
+
+#[test]
+fn parens_and_calls() {
+    assert_eq!(Prs::new("7 // 2").parse_f64().unwrap(), 3.0);
+    assert_eq!(Prs::new("gcd(12, 8)").parse_f64().unwrap(), 4.0);
+    assert_eq!(Prs::new("lcm(4, 6)").parse_f64().unwrap(), 12.0);
+    assert_eq!(Prs::new("(1 + 2) * 3").parse_f64().unwrap(), 9.0);
+    assert_eq!(
+        Prs::new("gcd(2 * 9, lcm(3, 4)) + 1").parse_f64().unwrap(),
+        7.0
+    );
+    assert_eq!(Prs::new("gcd(-12, 8)").parse_f64().unwrap(), 4.0);
+    assert_eq!(Prs::new("lcm(0, 5)").parse_f64().unwrap(), 0.0);
+    assert!(Prs::new("gcd(1.5, 2)").parse_f64().is_err());
+    assert!(Prs::new("gcd(1)").parse_f64().is_err());
+    assert!(Prs::new("foo(1, 2)").parse_f64().is_err());
+    assert!(Prs::new("(1 + 2").parse_f64().is_err());
+    assert_eq!(Prs::new("gcd(12, 8) * (2 + 1)").parse_int().unwrap(), 12);
+    assert_eq!(Prs::new("lcm(4, 6) - (1 - 3)").parse_int().unwrap(), 14);
+    let mut p = Prs::new("lcm(9223372036854775807, 2)");
+    assert!(p.parse_int().is_err());
+    let q = Prs::new("(1 m + 500 m) as km").parse_quantity().unwrap();
+    assert_eq!(q.val, 0.501);
+    assert!(Prs::new("1, (2, 3)").parse_tuple().is_err());
+    assert_eq!(
+        Prs::new("1, gcd(4, 6)").parse_tuple().unwrap(),
+        vec![1.0, 2.0]
+    );
+}
+
+#[test]
+fn complete_parens() {
+    assert!(!is_complete("(1 + 2"));
+    assert!(!is_complete("((1"));
+    assert!(!is_complete("(1 +"));
+    assert!(is_complete("(1 + 2)"));
+    assert!(!is_complete("gcd(4,"));
+    assert!(is_complete("{ (1) }"));
+}
+
+#[test]
+fn lone_point() {
+    assert!(Prs::new("..5").parse_f64().is_err());
+    assert!(Prs::new("1.2.3").parse_f64().is_err());
+    assert_eq!(Prs::new(".5 + 1").parse_f64().unwrap(), 1.5);
+    assert_eq!(Prs::new("1.").parse_f64().unwrap(), 1.0);
+}
+
+/// Runs every entry point on `s`; any of them may fail but none may panic.
+fn parse_all(s: &str) {
+    let _ = Prs::new(s).parse();
+    let _ = Prs::new(s).parse_int();
+    let _ = Prs::new(s).parse_quantity();
+    let _ = Prs::new(s).parse_duration();
+    let _ = Prs::new(s).parse_tuple();
+    let _ = is_complete(s);
+    let _ = detokenize(&tokenize(s));
+    if let Err(e) = Prs::new(s).parse() {
+        let _ = e.render(s);
+    }
+}
+
+const WORDS: &[&str] = &[
+    "1",
+    "0",
+    "9223372036854775807",
+    "1.5",
+    ".5",
+    "1_000",
+    "0x1p-",
+    "0x1p1024",
+    "m",
+    "km",
+    "s",
+    "h",
+    "+",
+    "-",
+    "*",
+    "/",
+    "//",
+    "^",
+    "mod",
+    "<",
+    "==",
+    "=",
+    "=>",
+    "(",
+    ")",
+    "{",
+    "}",
+    "|",
+    ",",
+    ";",
+    "x",
+    "ans",
+    "case",
+    "let",
+    "in",
+    "as",
+    "if",
+    "gcd",
+    "lcm",
+    "_",
+];
+
+proptest! {
+    #[test]
+    fn fuzz_chars(s in "[0-9._xXpP+*/^<>=!|{}();, abcdeghilmnost\\-\t\né]{0,40}") {
+        parse_all(&s);
+    }
+
+    #[test]
+    fn fuzz_words(words in prop::collection::vec(prop::sample::select(WORDS), 0..300)) {
+        parse_all(&words.join(" "));
+    }
+}
+
+#[test]
+fn deep_inputs() {
+    for s in [
+        "-".repeat(100_000) + "1",
+        "(".repeat(100_000),
+        "9".repeat(100_000),
+        "0x".to_string() + &"f".repeat(10_000),
+    ] {
+        parse_all(&s);
+    }
+}
+
+#[test]
+fn parse_returns_value_kinds() {
+    assert_eq!(Prs::new("1 + 2").parse().unwrap(), Value::Num(3.0));
+    assert_eq!(Prs::new("1 < 2").parse().unwrap().as_bool(), Some(true));
+    assert_eq!(
+        Prs::new("x = 2; x * 3 != 6").parse().unwrap(),
+        Value::Bool(false)
+    );
+    assert_eq!(
+        Prs::new("1, 2 + 3").parse().unwrap().as_tuple(),
+        Some(&[1.0, 5.0][..])
+    );
+    assert!(Prs::new("1, 2").parse_f64().is_err());
+    assert_eq!(Value::Int(4).as_f64(), Some(4.0));
+    assert_eq!(Value::Duration(1.5).as_duration(), Some(1.5));
+    assert_eq!(Value::Int(4).as_i64(), Some(4));
+    assert_eq!(Value::Num(1.0).as_i64(), None);
+    let q = Quantity {
+        val: 2.0,
+        dim: [1, 0, 0],
+    };
+    assert_eq!(Value::Quantity(q).as_quantity(), Some(q));
+    assert_eq!(Value::Bool(true).as_f64(), None);
+    assert_eq!(Value::Tuple(vec![1.0]).as_bool(), None);
+}
+
+#[test]
+fn lazy_if() {
+    assert_eq!(Prs::new("if(1, 2, 1/0)").parse_f64().unwrap(), 2.0);
+    assert_eq!(Prs::new("if(0, 1/0, 3)").parse_f64().unwrap(), 3.0);
+    assert_eq!(Prs::new("if(1 < 2, 5, nope) + 1").parse_f64().unwrap(), 6.0);
+    assert_eq!(
+        Prs::new("if(2 > 3, gcd(4, 1/0), if(1, 7, 8))")
+            .parse_f64()
+            .unwrap(),
+        7.0
+    );
+    assert_eq!(Prs::new("if(1, 2, { 1/0 })").parse_f64().unwrap(), 2.0);
+    assert!(Prs::new("if(1, 1/0, 2)").parse_f64().is_err());
+    assert!(Prs::new("if(1, 2, )").parse_f64().is_err());
+    assert!(Prs::new("if(1, 2)").parse_f64().is_err());
+    assert!(Prs::new("if(0, 2, 3").parse_f64().is_err());
+}